@@ -0,0 +1,125 @@
+//! Compares drawing the display one pixel at a time, as the emulator used to, with
+//! uploading it to a streaming texture and copying that once, as `Graphics::render`
+//! does. Both draw to a window canvas with SDL's default renderer, as the game does:
+//! once to an 800x600 window with a 64x32 logical size like the game window, and once
+//! to an unscaled 64x32 window to show the cost of the draw calls alone. The renderer
+//! SDL picked is printed first, since the results depend on it.
+//!
+//!     cargo bench --bench render
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::rect::{Point, Rect};
+use sdl2::render::Canvas;
+use sdl2::video::Window;
+use sdl2::VideoSubsystem;
+
+const WIDTH: usize = 64;
+const HEIGHT: usize = 32;
+const BYTES_PER_PIXEL: usize = 4;
+
+type Vram = [[u8; WIDTH]; HEIGHT];
+
+fn canvas(video: &VideoSubsystem, width: u32, height: u32) -> Canvas<Window> {
+    let window = video
+        .window("render bench", width, height)
+        .position_centered()
+        .build()
+        .unwrap();
+    let mut canvas = window.into_canvas().build().unwrap();
+    canvas
+        .set_logical_size(WIDTH as u32, HEIGHT as u32)
+        .unwrap();
+    canvas
+}
+
+/// A frame with about half its pixels lit, in a pattern that is not all runs.
+fn vram() -> Vram {
+    let mut vram = [[0; WIDTH]; HEIGHT];
+    for (y, row) in vram.iter_mut().enumerate() {
+        for (x, pixel) in row.iter_mut().enumerate() {
+            *pixel = ((x * 7 + y * 3) % 5 < 2) as u8;
+        }
+    }
+    vram
+}
+
+fn color(pixel: u8) -> Color {
+    if pixel > 0 {
+        Color::RGB(255, 255, 255)
+    } else {
+        Color::RGB(0, 0, 0)
+    }
+}
+
+fn draw_points(canvas: &mut Canvas<Window>, vram: &Vram) {
+    for (y, row) in vram.iter().enumerate() {
+        for (x, &pixel) in row.iter().enumerate() {
+            canvas.set_draw_color(color(pixel));
+            canvas.draw_point(Point::new(x as i32, y as i32)).unwrap();
+        }
+    }
+    canvas.present();
+}
+
+fn fill_rects(canvas: &mut Canvas<Window>, vram: &Vram) {
+    for (y, row) in vram.iter().enumerate() {
+        for (x, &pixel) in row.iter().enumerate() {
+            canvas.set_draw_color(color(pixel));
+            canvas.fill_rect(Rect::new(x as i32, y as i32, 1, 1)).unwrap();
+        }
+    }
+    canvas.present();
+}
+
+fn render(c: &mut Criterion) {
+    let video = sdl2::init().unwrap().video().unwrap();
+    render_to(c, &video, "800x600 window", 800, 600);
+    render_to(c, &video, "unscaled", WIDTH as u32, HEIGHT as u32);
+}
+
+fn render_to(c: &mut Criterion, video: &VideoSubsystem, name: &str, width: u32, height: u32) {
+    let vram = vram();
+    let mut group = c.benchmark_group(name);
+
+    let mut canvas = canvas(video, width, height);
+    eprintln!(
+        "{}: {} renderer, {} video driver",
+        name,
+        canvas.info().name,
+        video.current_video_driver()
+    );
+    group.bench_function("per-pixel draw_point", |b| {
+        b.iter(|| draw_points(&mut canvas, &vram))
+    });
+    group.bench_function("per-pixel fill_rect", |b| {
+        b.iter(|| fill_rects(&mut canvas, &vram))
+    });
+
+    let creator = canvas.texture_creator();
+    let mut texture = creator
+        .create_texture_streaming(PixelFormatEnum::RGBA32, WIDTH as u32, HEIGHT as u32)
+        .unwrap();
+    let mut framebuffer = vec![0; WIDTH * HEIGHT * BYTES_PER_PIXEL];
+    group.bench_function("streaming texture", |b| {
+        b.iter(|| {
+            for (&pixel, out) in vram
+                .iter()
+                .flatten()
+                .zip(framebuffer.chunks_exact_mut(BYTES_PER_PIXEL))
+            {
+                let color = color(pixel);
+                out.copy_from_slice(&[color.r, color.g, color.b, color.a]);
+            }
+            texture
+                .update(None, &framebuffer, WIDTH * BYTES_PER_PIXEL)
+                .unwrap();
+            canvas.copy(&texture, None, None).unwrap();
+            canvas.present();
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, render);
+criterion_main!(benches);
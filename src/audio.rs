@@ -1,59 +1,404 @@
-use sdl2;
-use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+use crate::wav::*;
+use sdl2::audio::{AudioQueue, AudioSpecDesired};
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, Write};
+use std::path::Path;
 
+pub const SAMPLE_RATE: u32 = 44100;
+/// Audio samples in one 60Hz frame of emulated time.
+pub const SAMPLES_PER_FRAME: usize = SAMPLE_RATE as usize / 60;
+
+/// Length of the fade in and out around each beep, which avoids clicks.
+const ATTACK_MILLIS: f32 = 5.0;
+const RELEASE_MILLIS: f32 = 10.0;
+
+/// Bits in an XO-CHIP audio pattern, played in a loop.
+const PATTERN_BITS: f32 = 128.0;
+/// Pitch register value at which the pattern plays at 4000 bits per second.
+const DEFAULT_PITCH: u8 = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Waveform {
+    Square,
+    Triangle,
+    Sine,
+    Noise,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ToneSettings {
+    pub frequency: f32,
+    /// From 0.0 (silent) to 1.0 (full scale).
+    pub volume: f32,
+    pub waveform: Waveform,
+}
+
+impl Default for ToneSettings {
+    fn default() -> Self {
+        ToneSettings {
+            frequency: 240.0,
+            volume: 0.25,
+            waveform: Waveform::Square,
+        }
+    }
+}
+
+/// Samples kept queued ahead of the device, as a number of frames. More adds
+/// latency, less risks running dry when a frame is late.
+const TARGET_LATENCY_FRAMES: usize = 3;
+/// Queued audio beyond this many frames is dropped, e.g. after the window was moved.
+const MAX_LATENCY_FRAMES: usize = 8;
+/// Largest change to a frame's sample count when correcting drift, as a fraction.
+const DRIFT_CORRECTION: usize = 50;
+
+/// Plays the beeper. Audio is generated a frame at a time by the emulation loop and
+/// queued to the device, so each beep lasts exactly as many frames as the sound timer.
 pub struct Audio {
-    device: AudioDevice<SquareWave>,
+    /// None when running headless, without a sound device.
+    queue: Option<AudioQueue<f32>>,
+    tone: ToneGenerator,
+    samples_per_frame: usize,
+    samples: Vec<f32>,
+    muted: bool,
 }
 
 impl Audio {
-    pub fn new(sdl_context: &sdl2::Sdl) -> Self {
+    /// Opens the sound device, or plays nothing if there is no SDL context.
+    pub fn new(sdl_context: Option<&sdl2::Sdl>, settings: ToneSettings) -> Self {
+        let sdl_context = match sdl_context {
+            Some(sdl_context) => sdl_context,
+            None => {
+                return Audio {
+                    queue: None,
+                    tone: ToneGenerator::new(SAMPLE_RATE, settings),
+                    samples_per_frame: SAMPLES_PER_FRAME,
+                    samples: Vec::new(),
+                    muted: false,
+                }
+            }
+        };
         let audio_subsystem = sdl_context.audio().unwrap();
 
         let desired_spec = AudioSpecDesired {
-            freq: Some(44100),
+            freq: Some(SAMPLE_RATE as i32),
             channels: Some(1), // mono
             samples: None,     // default sample size
         };
 
-        let device = audio_subsystem
-            .open_playback(None, &desired_spec, |spec| {
-                // Show obtained AudioSpec
-                println!("{:?}", spec);
-
-                // initialize the audio callback
-                SquareWave {
-                    phase_inc: 240.0 / spec.freq as f32,
-                    phase: 0.0,
-                    volume: 0.25,
-                }
-            })
+        let queue = audio_subsystem
+            .open_queue::<f32, _>(None, &desired_spec)
             .unwrap();
+        // Show obtained AudioSpec
+        println!("{:?}", queue.spec());
+
+        let sample_rate = queue.spec().freq as u32;
+        let samples_per_frame = sample_rate as usize / 60;
+        queue.resume();
+
+        Audio {
+            queue: Some(queue),
+            tone: ToneGenerator::new(sample_rate, settings),
+            samples_per_frame,
+            samples: Vec::with_capacity(samples_per_frame * 2),
+            muted: false,
+        }
+    }
+
+    /// Queues one frame of audio, with the tone on or off for the whole frame.
+    pub fn play_frame(&mut self, sound_on: bool) {
+        let queue = match &self.queue {
+            Some(queue) => queue,
+            None => return,
+        };
+        let queued = queue.size() as usize / std::mem::size_of::<f32>();
+        if queued > self.samples_per_frame * MAX_LATENCY_FRAMES {
+            queue.clear();
+        }
+        let count = frame_sample_count(self.samples_per_frame, queued);
+
+        self.tone.set_gate(sound_on && !self.muted);
+        self.samples.clear();
+        for _ in 0..count {
+            self.samples.push(self.tone.next_sample());
+        }
+        queue.queue(&self.samples);
+    }
 
-        Audio { device: device }
+    pub fn set_pattern(&mut self, pattern: [u8; 16]) {
+        self.tone.set_pattern(pattern);
     }
 
-    pub fn start_beep(&self) {
-        self.device.resume();
+    pub fn set_pitch(&mut self, pitch: u8) {
+        self.tone.set_pitch(pitch);
     }
-    pub fn stop_beep(&self) {
-        self.device.pause();
+
+    pub fn toggle_mute(&mut self) {
+        self.muted = !self.muted;
+        println!("Sound {}", if self.muted { "muted" } else { "on" });
     }
 }
 
-struct SquareWave {
+/// How many samples to generate for a frame given how many are still queued. The
+/// emulation loop and the audio device run on different clocks, so the count is
+/// nudged to keep the queue near its target instead of drifting empty or full.
+fn frame_sample_count(samples_per_frame: usize, queued: usize) -> usize {
+    let target = samples_per_frame * TARGET_LATENCY_FRAMES;
+    let step = samples_per_frame / DRIFT_CORRECTION;
+    if queued < samples_per_frame {
+        // About to run dry: refill up to the target at once.
+        target - queued
+    } else if queued < target {
+        samples_per_frame + step
+    } else if queued > target + samples_per_frame {
+        samples_per_frame - step
+    } else {
+        samples_per_frame
+    }
+}
+
+/// Generates the beeper's tone, fading it in when the gate opens and out when it
+/// closes. Once an XO-CHIP audio pattern is loaded, the pattern's bits are played
+/// instead of the configured waveform.
+pub struct ToneGenerator {
+    settings: ToneSettings,
+    sample_rate: f32,
     phase_inc: f32,
     phase: f32,
-    volume: f32,
+    gate: bool,
+    envelope: f32,
+    attack_step: f32,
+    release_step: f32,
+    noise_state: u32,
+    noise_value: f32,
+    pattern: Option<[u8; 16]>,
+    pattern_position: f32,
+    pattern_step: f32,
 }
 
-impl AudioCallback for SquareWave {
-    type Channel = f32;
+impl ToneGenerator {
+    pub fn new(sample_rate: u32, settings: ToneSettings) -> Self {
+        let samples_per_milli = sample_rate as f32 / 1000.0;
+        ToneGenerator {
+            settings,
+            sample_rate: sample_rate as f32,
+            phase_inc: settings.frequency / sample_rate as f32,
+            phase: 0.0,
+            gate: false,
+            envelope: 0.0,
+            attack_step: 1.0 / (ATTACK_MILLIS * samples_per_milli),
+            release_step: 1.0 / (RELEASE_MILLIS * samples_per_milli),
+            noise_state: 0x1234_5678,
+            noise_value: 1.0,
+            pattern: None,
+            pattern_position: 0.0,
+            pattern_step: pattern_rate(DEFAULT_PITCH) / sample_rate as f32,
+        }
+    }
+
+    pub fn set_pattern(&mut self, pattern: [u8; 16]) {
+        self.pattern = Some(pattern);
+    }
+
+    pub fn set_pitch(&mut self, pitch: u8) {
+        self.pattern_step = pattern_rate(pitch) / self.sample_rate;
+    }
+
+    pub fn set_gate(&mut self, gate: bool) {
+        self.gate = gate;
+    }
+
+    pub fn next_sample(&mut self) -> f32 {
+        self.envelope = if self.gate {
+            (self.envelope + self.attack_step).min(1.0)
+        } else {
+            (self.envelope - self.release_step).max(0.0)
+        };
+
+        if let Some(pattern) = &self.pattern {
+            let bit = self.pattern_position as usize;
+            let level = if pattern[bit / 8] & (0x80 >> (bit % 8)) != 0 {
+                1.0
+            } else {
+                -1.0
+            };
+            self.pattern_position = (self.pattern_position + self.pattern_step) % PATTERN_BITS;
+            return self.settings.volume * self.envelope * level;
+        }
+
+        let wave = match self.settings.waveform {
+            Waveform::Square => {
+                if self.phase < 0.5 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+            Waveform::Triangle => 1.0 - 4.0 * (self.phase - 0.5).abs(),
+            Waveform::Sine => (self.phase * std::f32::consts::TAU).sin(),
+            Waveform::Noise => self.noise_value,
+        };
+
+        // Noise holds a random level for each half cycle, so the frequency still sets
+        // its pitch.
+        let next_phase = self.phase + self.phase_inc;
+        if next_phase >= 1.0 || (self.phase < 0.5 && next_phase >= 0.5) {
+            self.noise_value = self.next_noise();
+        }
+        self.phase = next_phase % 1.0;
+
+        self.settings.volume * self.envelope * wave
+    }
+
+    /// A xorshift generator mapped to -1.0..1.0; deterministic so captures repeat.
+    fn next_noise(&mut self) -> f32 {
+        let mut x = self.noise_state;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.noise_state = x;
+        x as f32 / u32::MAX as f32 * 2.0 - 1.0
+    }
+}
+
+/// Bits per second played from the audio pattern for a pitch register value.
+fn pattern_rate(pitch: u8) -> f32 {
+    4000.0 * 2f32.powf((pitch as f32 - 64.0) / 48.0)
+}
+
+/// Records the beeper to a WAV file in emulated time: each frame adds
+/// `SAMPLES_PER_FRAME` samples of tone or silence depending on the sound timer, so
+/// the output does not depend on the host's audio device or timing.
+pub struct AudioCapture<W: Write + Seek> {
+    tone: ToneGenerator,
+    wav: WavWriter<W>,
+    samples: Vec<f32>,
+}
+
+impl AudioCapture<BufWriter<File>> {
+    pub fn create(path: &Path, settings: ToneSettings) -> io::Result<Self> {
+        AudioCapture::new(BufWriter::new(File::create(path)?), settings)
+    }
+}
+
+impl<W: Write + Seek> AudioCapture<W> {
+    pub fn new(out: W, settings: ToneSettings) -> io::Result<Self> {
+        Ok(AudioCapture {
+            tone: ToneGenerator::new(SAMPLE_RATE, settings),
+            wav: WavWriter::new(out, SAMPLE_RATE)?,
+            samples: vec![0.0; SAMPLES_PER_FRAME],
+        })
+    }
+
+    pub fn add_frame(&mut self, sound_on: bool) -> io::Result<()> {
+        self.tone.set_gate(sound_on);
+        for sample in self.samples.iter_mut() {
+            *sample = self.tone.next_sample();
+        }
+        self.wav.write_samples(&self.samples)
+    }
+
+    pub fn set_pattern(&mut self, pattern: [u8; 16]) {
+        self.tone.set_pattern(pattern);
+    }
+
+    pub fn set_pitch(&mut self, pitch: u8) {
+        self.tone.set_pitch(pitch);
+    }
+
+    pub fn finish(self) -> io::Result<W> {
+        self.wav.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn capture(settings: ToneSettings, frames: &[bool]) -> Vec<i16> {
+        let mut capture = AudioCapture::new(Cursor::new(Vec::new()), settings).unwrap();
+        for &sound_on in frames {
+            capture.add_frame(sound_on).unwrap();
+        }
+        let bytes = capture.finish().unwrap().into_inner();
+        bytes[44..]
+            .chunks_exact(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]))
+            .collect()
+    }
+
+    #[test]
+    fn capture_writes_tone_only_while_sound_is_on() {
+        let samples = capture(ToneSettings::default(), &[false, true, false, false]);
+        assert_eq!(samples.len(), 4 * SAMPLES_PER_FRAME);
+        let frames: Vec<&[i16]> = samples.chunks(SAMPLES_PER_FRAME).collect();
+
+        assert!(frames[0].iter().all(|&s| s == 0));
+        assert!(frames[1].iter().any(|&s| s > 0));
+        assert!(frames[1].iter().any(|&s| s < 0));
+        assert!(frames[3].iter().all(|&s| s == 0));
+    }
+
+    #[test]
+    fn beep_fades_in_without_click() {
+        let samples = capture(ToneSettings::default(), &[true]);
+        let full_scale = (ToneSettings::default().volume * i16::MAX as f32) as i16;
+        assert!(samples[0].abs() < full_scale / 10);
+        assert_eq!(samples[SAMPLES_PER_FRAME / 2].abs(), full_scale);
+    }
+
+    #[test]
+    fn frame_sample_count_corrects_drift() {
+        let frame = SAMPLES_PER_FRAME;
+        let target = frame * TARGET_LATENCY_FRAMES;
+        assert_eq!(frame_sample_count(frame, target), frame);
+        assert!(frame_sample_count(frame, target - 1) > frame);
+        assert!(frame_sample_count(frame, target + frame + 1) < frame);
+        assert_eq!(frame_sample_count(frame, 0), target);
+    }
+
+    #[test]
+    fn pattern_rate_doubles_every_48_steps() {
+        assert_eq!(pattern_rate(64), 4000.0);
+        assert!((pattern_rate(112) - 8000.0).abs() < 0.01);
+        assert!((pattern_rate(16) - 2000.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn pattern_plays_bits_and_loops() {
+        let mut tone = ToneGenerator::new(8000, ToneSettings::default());
+        // 4000 bits per second at 8000Hz: two samples per bit.
+        let mut pattern = [0; 16];
+        pattern[0] = 0b1010_0000;
+        tone.set_pattern(pattern);
+        tone.set_gate(true);
+        tone.envelope = 1.0;
+        let levels: Vec<bool> = (0..258).map(|_| tone.next_sample() > 0.0).collect();
+
+        assert_eq!(
+            &levels[0..8],
+            &[true, true, false, false, true, true, false, false]
+        );
+        assert!(levels[8..256].iter().all(|&high| !high));
+        assert_eq!(&levels[256..258], &[true, true]);
+    }
 
-    fn callback(&mut self, out: &mut [f32]) {
-        // Generate a square wave
-        for x in out.iter_mut() {
-            *x = self.volume * if self.phase < 0.5 { 1.0 } else { -1.0 };
-            self.phase = (self.phase + self.phase_inc) % 1.0;
+    #[test]
+    fn waveforms_stay_within_volume() {
+        for &waveform in &[
+            Waveform::Square,
+            Waveform::Triangle,
+            Waveform::Sine,
+            Waveform::Noise,
+        ] {
+            let settings = ToneSettings {
+                frequency: 440.0,
+                volume: 0.5,
+                waveform,
+            };
+            let samples = capture(settings, &[true, true]);
+            let peak = samples.iter().map(|s| s.abs()).max().unwrap();
+            assert!(peak > 0 && peak <= i16::MAX / 2, "{:?}", waveform);
         }
     }
 }
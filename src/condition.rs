@@ -0,0 +1,286 @@
+use crate::debugger::*;
+use snafu::Snafu;
+
+#[derive(Debug, Snafu)]
+pub enum ConditionError {
+    #[snafu(display("Invalid condition `{}`: {}", text, reason))]
+    Invalid { text: String, reason: String },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(u32),
+    Name(String),
+    Operator(&'static str),
+}
+
+/// Operators, longest first so `<=` is not read as `<`.
+const OPERATORS: [&str; 17] = [
+    "==", "!=", "<=", ">=", "&&", "||", "<", ">", "+", "-", "&", "|", "!", "(", ")", "[", "]",
+];
+
+#[derive(Clone, Debug, PartialEq)]
+enum Expr {
+    Number(u32),
+    Register(Register),
+    /// The byte at an address.
+    Memory(Box<Expr>),
+    Not(Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+}
+
+/// A condition on the machine state, such as `V3 == 0x10 && I > 0x300`. Registers
+/// are named as in the debugger (`v0` to `vf`, `i`, `pc`, `sp`, `dt`, `st`), `[addr]`
+/// reads a byte of memory, and numbers are decimal or `0x` hex. `+`, `-`, `&` and `|`
+/// bind tightest, then comparisons, then `&&` and `||`; comparisons and logic give 1
+/// or 0.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Condition {
+    expr: Expr,
+}
+
+impl Condition {
+    pub fn parse(text: &str) -> Result<Condition, ConditionError> {
+        let invalid = |reason: String| ConditionError::Invalid {
+            text: text.to_string(),
+            reason,
+        };
+        let tokens = tokenize(text).map_err(invalid)?;
+        let mut parser = Parser { tokens, next: 0 };
+        let expr = parser.or().map_err(invalid)?;
+        if let Some(token) = parser.peek() {
+            return Err(invalid(format!("unexpected {}", describe(token))));
+        }
+        Ok(Condition { expr })
+    }
+
+    pub fn evaluate(&self, target: &dyn Target) -> bool {
+        evaluate(&self.expr, target) != 0
+    }
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = text.trim_start();
+    while !rest.is_empty() {
+        if let Some(operator) = OPERATORS.iter().find(|op| rest.starts_with(*op)) {
+            tokens.push(Token::Operator(operator));
+            rest = &rest[operator.len()..];
+        } else {
+            let end = rest
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(rest.len());
+            if end == 0 {
+                let unexpected = rest.chars().next().unwrap_or_default();
+                return Err(format!("unexpected `{}`", unexpected));
+            }
+            let word = &rest[..end];
+            let token = if word.starts_with(|c: char| c.is_ascii_digit()) {
+                let number = match word.strip_prefix("0x") {
+                    Some(hex) => u32::from_str_radix(hex, 16),
+                    None => word.parse(),
+                };
+                Token::Number(number.map_err(|_| format!("bad number {}", word))?)
+            } else {
+                Token::Name(word.to_lowercase())
+            };
+            tokens.push(token);
+            rest = &rest[end..];
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Number(number) => format!("number {}", number),
+        Token::Name(name) => format!("name {}", name),
+        Token::Operator(operator) => format!("`{}`", operator),
+    }
+}
+
+/// Recursive descent parser, one method per precedence level.
+struct Parser {
+    tokens: Vec<Token>,
+    next: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next)
+    }
+
+    /// Consumes the next token if it is one of `operators`.
+    fn operator(&mut self, operators: &[&'static str]) -> Option<&'static str> {
+        match self.peek() {
+            Some(Token::Operator(operator)) if operators.contains(operator) => {
+                let operator = *operator;
+                self.next += 1;
+                Some(operator)
+            }
+            _ => None,
+        }
+    }
+
+    fn expect(&mut self, operator: &'static str) -> Result<(), String> {
+        match self.operator(&[operator]) {
+            Some(_) => Ok(()),
+            None => Err(format!("expected `{}`", operator)),
+        }
+    }
+
+    fn binary(
+        &mut self,
+        operators: &[&'static str],
+        operand: fn(&mut Parser) -> Result<Expr, String>,
+    ) -> Result<Expr, String> {
+        let mut left = operand(self)?;
+        while let Some(operator) = self.operator(operators) {
+            let right = operand(self)?;
+            left = Expr::Binary(operator, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        self.binary(&["||"], Parser::and)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        self.binary(&["&&"], Parser::comparison)
+    }
+
+    fn comparison(&mut self) -> Result<Expr, String> {
+        self.binary(&["==", "!=", "<=", ">=", "<", ">"], Parser::sum)
+    }
+
+    fn sum(&mut self) -> Result<Expr, String> {
+        self.binary(&["+", "-", "&", "|"], Parser::unary)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.operator(&["!"]).is_some() {
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+        if self.operator(&["("]).is_some() {
+            let expr = self.or()?;
+            self.expect(")")?;
+            return Ok(expr);
+        }
+        if self.operator(&["["]).is_some() {
+            let address = self.or()?;
+            self.expect("]")?;
+            return Ok(Expr::Memory(Box::new(address)));
+        }
+        let token = self.peek().cloned().ok_or("unexpected end")?;
+        self.next += 1;
+        match token {
+            Token::Number(number) => Ok(Expr::Number(number)),
+            Token::Name(name) => Register::all()
+                .into_iter()
+                .find(|register| register.name() == name)
+                .map(Expr::Register)
+                .ok_or_else(|| format!("unknown register {}", name)),
+            token => Err(format!("unexpected {}", describe(&token))),
+        }
+    }
+}
+
+fn evaluate(expr: &Expr, target: &dyn Target) -> u32 {
+    match expr {
+        Expr::Number(number) => *number,
+        Expr::Register(register) => target.read_register(*register) as u32,
+        Expr::Memory(address) => {
+            let address = evaluate(address, target) as usize;
+            target.read_memory(address, 1).first().cloned().unwrap_or(0) as u32
+        }
+        Expr::Not(operand) => (evaluate(operand, target) == 0) as u32,
+        Expr::Binary(operator, left, right) => {
+            let left = evaluate(left, target);
+            // `&&` and `||` only look at the right side when they need to.
+            match *operator {
+                "&&" => return (left != 0 && evaluate(right, target) != 0) as u32,
+                "||" => return (left != 0 || evaluate(right, target) != 0) as u32,
+                _ => {}
+            }
+            let right = evaluate(right, target);
+            match *operator {
+                "==" => (left == right) as u32,
+                "!=" => (left != right) as u32,
+                "<" => (left < right) as u32,
+                "<=" => (left <= right) as u32,
+                ">" => (left > right) as u32,
+                ">=" => (left >= right) as u32,
+                "+" => left.wrapping_add(right),
+                "-" => left.wrapping_sub(right),
+                "&" => left & right,
+                "|" => left | right,
+                _ => unreachable!("parser only builds known operators"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::debugger::testing::TestTarget;
+
+    fn check(text: &str, target: &TestTarget) -> bool {
+        Condition::parse(text).unwrap().evaluate(target)
+    }
+
+    #[test]
+    fn registers_and_comparisons() {
+        let mut target = TestTarget::new();
+        target.write_register(Register::V(3), 0x10);
+        target.write_register(Register::I, 0x301);
+        assert!(check("V3 == 0x10 && I > 0x300", &target));
+        assert!(!check("v3 == 16 && i <= 0x300", &target));
+        assert!(check("v3 != 16 || !(i < 0x300)", &target));
+        assert!(check("(v3 + 1) & 0xff == 17", &target));
+    }
+
+    #[test]
+    fn memory_reads() {
+        let mut target = TestTarget::new();
+        target.write_memory(0x302, &[0xab]);
+        target.write_register(Register::I, 0x300);
+        assert!(check("[i + 2] == 0xab", &target));
+        assert!(check("[0x5000] == 0", &target));
+    }
+
+    #[test]
+    fn errors_name_the_problem() {
+        let message = |text| Condition::parse(text).unwrap_err().to_string();
+        assert_eq!(
+            message("v16 == 1"),
+            "Invalid condition `v16 == 1`: unknown register v16"
+        );
+        assert_eq!(
+            message("(v1 == 2"),
+            "Invalid condition `(v1 == 2`: expected `)`"
+        );
+        assert_eq!(
+            message("v1 =="),
+            "Invalid condition `v1 ==`: unexpected end"
+        );
+        assert_eq!(
+            message("v1 2"),
+            "Invalid condition `v1 2`: unexpected number 2"
+        );
+        assert_eq!(
+            message("v1 # 2"),
+            "Invalid condition `v1 # 2`: unexpected `#`"
+        );
+        assert_eq!(
+            message("V3 == é"),
+            "Invalid condition `V3 == é`: unexpected `é`"
+        );
+        assert_eq!(
+            message("V3 ≥ 2"),
+            "Invalid condition `V3 ≥ 2`: unexpected `≥`"
+        );
+    }
+}
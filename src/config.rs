@@ -0,0 +1,926 @@
+use crate::audio::*;
+use crate::filter::*;
+use crate::gamepad::*;
+use crate::graphics::DisplayMode;
+use crate::input::{Hotkey, KeyWaitMode};
+use crate::instruction::MNEMONICS;
+use crate::keymap::*;
+use crate::palette::*;
+use crate::recorder::FrameFormat;
+use crate::symbols::symbols_beside;
+use crate::trace::TraceFilter;
+use sdl2::keyboard::Keycode;
+use snafu::{OptionExt, ResultExt, Snafu};
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Snafu)]
+pub enum ConfigError {
+    #[snafu(display("Help requested"))]
+    HelpRequested,
+    #[snafu(display("Missing ROM file"))]
+    MissingRom,
+    #[snafu(display("Unknown option: {}", name))]
+    UnknownOption { name: String },
+    #[snafu(display("Option {} requires a value", name))]
+    MissingValue { name: String },
+    #[snafu(display("Invalid value for {}: {}", name, value))]
+    InvalidValue { name: String, value: String },
+    #[snafu(display("Could not read config file {}: {}", path, source))]
+    ReadConfig {
+        path: String,
+        source: std::io::Error,
+    },
+    #[snafu(display("Unknown key name for {}: {}", name, value))]
+    UnknownKeyName { name: String, value: String },
+    #[snafu(display("{}:{}: expected `key = value` or `[rom_file]`", path, line))]
+    BadConfigLine { path: String, line: usize },
+    #[snafu(display("Config file {} includes itself", path))]
+    IncludeLoop { path: String },
+    #[snafu(display("Options {} and {} cannot be used together", first, second))]
+    ConflictingOptions { first: String, second: String },
+}
+
+/// Emulator settings, read from the command line and optional config files. A config
+/// file holds one `option = value` per line, using the long option names without
+/// the leading dashes; lines starting with `#` are comments. Options after a
+/// `[rom_file]` line only apply when running a ROM with that file name.
+#[derive(Debug, Default)]
+pub struct Config {
+    pub rom_path: String,
+    theme: Option<String>,
+    foreground: Option<String>,
+    background: Option<String>,
+    display: Option<String>,
+    decay: Option<String>,
+    filter: Option<String>,
+    scanlines: bool,
+    grid: bool,
+    screenshot_dir: Option<String>,
+    pub record_gif: Option<PathBuf>,
+    pub dump_frames: Option<PathBuf>,
+    frame_format: Option<String>,
+    pub record_audio: Option<PathBuf>,
+    pub keypad: bool,
+    tone_hz: Option<String>,
+    volume: Option<String>,
+    waveform: Option<String>,
+    layout: Option<String>,
+    key_bindings: Vec<(String, String)>,
+    pad_bindings: Vec<(String, String)>,
+    pad_deadzone: Option<String>,
+    key_wait: Option<String>,
+    turbo_rate: Option<String>,
+    gdb: Option<String>,
+    dap: Option<String>,
+    pub trace: Option<PathBuf>,
+    trace_pc: Option<String>,
+    trace_only: Option<String>,
+    trace_last: Option<String>,
+    crash_dir: Option<String>,
+    pub load_state: Option<PathBuf>,
+    pub debug_window: bool,
+    symbols: Option<PathBuf>,
+    headless: Option<String>,
+    /// Config files being read, innermost last, to catch files that include themselves.
+    loading: Vec<PathBuf>,
+}
+
+impl Config {
+    pub fn from_args(args: &[String]) -> Result<Config, ConfigError> {
+        let mut config = Config::default();
+        let mut rom_path = None;
+        let mut options = Vec::new();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if arg == "-h" || arg == "--help" {
+                return HelpRequested.fail();
+            }
+            match arg.strip_prefix("--") {
+                Some(name) => {
+                    let value = args.next().context(MissingValue { name })?;
+                    options.push((name, value));
+                }
+                None => rom_path = Some(arg.clone()),
+            }
+        }
+        // The ROM is needed first to pick out its sections in config files.
+        config.rom_path = rom_path.unwrap_or_default();
+        for (name, value) in options {
+            config.set(name, value)?;
+        }
+        // A debug adapter client can provide the ROM when it launches, and a save state
+        // has the whole of memory.
+        if config.rom_path.is_empty() && config.dap.is_none() && config.load_state.is_none() {
+            return MissingRom.fail();
+        }
+        if config.gdb.is_some() && config.dap.is_some() {
+            return ConflictingOptions {
+                first: "gdb",
+                second: "dap",
+            }
+            .fail();
+        }
+        // Nothing listens for debuggers without the event loop.
+        if config.headless.is_some() && (config.gdb.is_some() || config.dap.is_some()) {
+            let debugger = if config.gdb.is_some() { "gdb" } else { "dap" };
+            return ConflictingOptions {
+                first: "headless",
+                second: debugger,
+            }
+            .fail();
+        }
+        config.palette()?;
+        config.display_mode()?;
+        config.filters()?;
+        config.frame_format()?;
+        config.tone_settings()?;
+        config.keymap()?;
+        config.pad_map()?;
+        config.key_wait_mode()?;
+        config.turbo_rate()?;
+        config.gdb_address()?;
+        config.dap_address()?;
+        config.trace_filter()?;
+        config.trace_last()?;
+        config.headless_frames()?;
+
+        Ok(config)
+    }
+
+    pub fn palette(&self) -> Result<Palette, ConfigError> {
+        let mut palette = match &self.theme {
+            Some(name) => Palette::from_theme(name).context(InvalidValue {
+                name: "theme",
+                value: name,
+            })?,
+            None => Palette::default(),
+        };
+        if let Some(value) = &self.foreground {
+            let color = parse_color(value).context(InvalidValue { name: "fg", value })?;
+            palette.set_foreground(color);
+        }
+        if let Some(value) = &self.background {
+            let color = parse_color(value).context(InvalidValue { name: "bg", value })?;
+            palette.set_background(color);
+        }
+
+        Ok(palette)
+    }
+
+    pub fn display_mode(&self) -> Result<DisplayMode, ConfigError> {
+        let decay = match &self.decay {
+            Some(value) => value
+                .parse::<f32>()
+                .ok()
+                .filter(|decay| *decay >= 0.0 && *decay < 1.0)
+                .context(InvalidValue {
+                    name: "decay",
+                    value,
+                })?,
+            None => 0.6,
+        };
+        match self.display.as_deref() {
+            None | Some("raw") => Ok(DisplayMode::Raw),
+            Some("fade") => Ok(DisplayMode::Fade { decay }),
+            Some("blend") => Ok(DisplayMode::Blend),
+            Some(value) => InvalidValue {
+                name: "display",
+                value,
+            }
+            .fail(),
+        }
+    }
+
+    pub fn filters(&self) -> Result<Filters, ConfigError> {
+        let upscaler = match self.filter.as_deref() {
+            None | Some("none") => Upscaler::None,
+            Some("scale2x") => Upscaler::Scale2x,
+            Some("scale3x") => Upscaler::Scale3x,
+            Some(value) => {
+                return InvalidValue {
+                    name: "filter",
+                    value,
+                }
+                .fail()
+            }
+        };
+
+        Ok(Filters {
+            upscaler,
+            scanlines: self.scanlines,
+            grid: self.grid,
+        })
+    }
+
+    pub fn screenshot_dir(&self) -> PathBuf {
+        PathBuf::from(self.screenshot_dir.as_deref().unwrap_or("screenshots"))
+    }
+
+    pub fn crash_dir(&self) -> PathBuf {
+        PathBuf::from(self.crash_dir.as_deref().unwrap_or("crashes"))
+    }
+
+    /// The symbol file given, or the one next to the ROM.
+    pub fn symbols_path(&self) -> Option<PathBuf> {
+        match &self.symbols {
+            Some(path) => Some(path.clone()),
+            None if !self.rom_path.is_empty() => symbols_beside(Path::new(&self.rom_path)),
+            None => None,
+        }
+    }
+
+    pub fn frame_format(&self) -> Result<FrameFormat, ConfigError> {
+        match self.frame_format.as_deref() {
+            None | Some("png") => Ok(FrameFormat::Png),
+            Some("ppm") => Ok(FrameFormat::Ppm),
+            Some(value) => InvalidValue {
+                name: "frame-format",
+                value,
+            }
+            .fail(),
+        }
+    }
+
+    pub fn tone_settings(&self) -> Result<ToneSettings, ConfigError> {
+        let mut settings = ToneSettings::default();
+        if let Some(value) = &self.tone_hz {
+            settings.frequency = value
+                .parse::<f32>()
+                .ok()
+                .filter(|hz| *hz > 0.0 && *hz < SAMPLE_RATE as f32 / 2.0)
+                .context(InvalidValue {
+                    name: "tone-hz",
+                    value,
+                })?;
+        }
+        if let Some(value) = &self.volume {
+            settings.volume = value
+                .parse::<f32>()
+                .ok()
+                .filter(|volume| *volume >= 0.0 && *volume <= 1.0)
+                .context(InvalidValue {
+                    name: "volume",
+                    value,
+                })?;
+        }
+        settings.waveform = match self.waveform.as_deref() {
+            None | Some("square") => Waveform::Square,
+            Some("triangle") => Waveform::Triangle,
+            Some("sine") => Waveform::Sine,
+            Some("noise") => Waveform::Noise,
+            Some(value) => {
+                return InvalidValue {
+                    name: "waveform",
+                    value,
+                }
+                .fail()
+            }
+        };
+
+        Ok(settings)
+    }
+
+    pub fn keymap(&self) -> Result<KeyMap, ConfigError> {
+        let mut keymap = match &self.layout {
+            Some(layout) => KeyMap::preset(layout).context(InvalidValue {
+                name: "layout",
+                value: layout,
+            })?,
+            None => KeyMap::default(),
+        };
+        for (name, value) in &self.key_bindings {
+            let keycode = Keycode::from_name(value).context(UnknownKeyName { name, value })?;
+            if let Some(digit) = name.strip_prefix("key-") {
+                keymap.bind_key(parse_key_digit(name, digit)?, keycode);
+            } else if let Some(digit) = name.strip_prefix("turbo-") {
+                keymap.bind_turbo(parse_key_digit(name, digit)?, keycode);
+            } else if let Some(hotkey) = name.strip_prefix("hotkey-") {
+                let hotkey: Hotkey =
+                    KeyMap::hotkey_by_name(hotkey).context(UnknownOption { name })?;
+                keymap.bind_hotkey(hotkey, keycode);
+            }
+        }
+
+        Ok(keymap)
+    }
+
+    pub fn pad_map(&self) -> Result<PadMap, ConfigError> {
+        let mut map = PadMap::default();
+        if let Some(value) = &self.pad_deadzone {
+            map.deadzone = value
+                .parse::<i16>()
+                .ok()
+                .filter(|deadzone| *deadzone >= 0)
+                .context(InvalidValue {
+                    name: "pad-deadzone",
+                    value,
+                })?;
+        }
+        for (name, value) in &self.pad_bindings {
+            let input = name
+                .strip_prefix("pad-")
+                .and_then(PadInput::from_name)
+                .context(UnknownOption { name })?;
+            let key = usize::from_str_radix(value, 16)
+                .ok()
+                .filter(|key| *key < 16)
+                .context(InvalidValue { name, value })?;
+            map.bind(input, key);
+        }
+
+        Ok(map)
+    }
+
+    pub fn key_wait_mode(&self) -> Result<KeyWaitMode, ConfigError> {
+        match &self.key_wait {
+            Some(value) => KeyWaitMode::from_name(value).context(InvalidValue {
+                name: "key-wait",
+                value,
+            }),
+            None => Ok(KeyWaitMode::Release),
+        }
+    }
+
+    /// Autofire presses per second.
+    pub fn turbo_rate(&self) -> Result<u32, ConfigError> {
+        match &self.turbo_rate {
+            Some(value) => value
+                .parse::<u32>()
+                .ok()
+                .filter(|rate| (1..=30).contains(rate))
+                .context(InvalidValue {
+                    name: "turbo-rate",
+                    value,
+                }),
+            None => Ok(10),
+        }
+    }
+
+    /// Where to serve the GDB remote protocol, if anywhere.
+    pub fn gdb_address(&self) -> Result<Option<SocketAddr>, ConfigError> {
+        match &self.gdb {
+            Some(value) => value
+                .parse()
+                .ok()
+                .map(Some)
+                .context(InvalidValue { name: "gdb", value }),
+            None => Ok(None),
+        }
+    }
+
+    /// Where to serve the Debug Adapter Protocol, if anywhere.
+    pub fn dap_address(&self) -> Result<Option<SocketAddr>, ConfigError> {
+        match &self.dap {
+            Some(value) => value
+                .parse()
+                .ok()
+                .map(Some)
+                .context(InvalidValue { name: "dap", value }),
+            None => Ok(None),
+        }
+    }
+
+    pub fn trace_filter(&self) -> Result<TraceFilter, ConfigError> {
+        let mut filter = TraceFilter::default();
+        if let Some(value) = &self.trace_pc {
+            let range = value
+                .split_once('-')
+                .and_then(|(first, last)| {
+                    let first = u16::from_str_radix(first.trim(), 16).ok()?;
+                    let last = u16::from_str_radix(last.trim(), 16).ok()?;
+                    Some((first, last))
+                })
+                .filter(|(first, last)| first <= last && *last <= 0xfff);
+            filter.pc_range = Some(range.context(InvalidValue {
+                name: "trace-pc",
+                value,
+            })?);
+        }
+        if let Some(value) = &self.trace_only {
+            for mnemonic in value.split(',') {
+                let mnemonic = mnemonic.trim().to_uppercase();
+                if !MNEMONICS.contains(&mnemonic.as_str()) {
+                    return InvalidValue {
+                        name: "trace-only",
+                        value,
+                    }
+                    .fail();
+                }
+                filter.mnemonics.push(mnemonic);
+            }
+        }
+
+        Ok(filter)
+    }
+
+    /// How many instructions to keep for writing to the trace when the machine stops,
+    /// if the trace should not be written as it runs.
+    pub fn trace_last(&self) -> Result<Option<usize>, ConfigError> {
+        match &self.trace_last {
+            Some(value) => value
+                .parse::<usize>()
+                .ok()
+                .filter(|last| *last > 0)
+                .map(Some)
+                .context(InvalidValue {
+                    name: "trace-last",
+                    value,
+                }),
+            None => Ok(None),
+        }
+    }
+
+    /// How many frames to run without a window or sound device, if the run is headless.
+    pub fn headless_frames(&self) -> Result<Option<usize>, ConfigError> {
+        match &self.headless {
+            Some(value) => value
+                .parse::<usize>()
+                .ok()
+                .filter(|frames| *frames > 0)
+                .map(Some)
+                .context(InvalidValue {
+                    name: "headless",
+                    value,
+                }),
+            None => Ok(None),
+        }
+    }
+
+    fn set(&mut self, name: &str, value: &str) -> Result<(), ConfigError> {
+        match name {
+            "config" => self.load_file(value)?,
+            "theme" => self.theme = Some(value.to_string()),
+            "fg" => self.foreground = Some(value.to_string()),
+            "bg" => self.background = Some(value.to_string()),
+            "display" => self.display = Some(value.to_string()),
+            "decay" => self.decay = Some(value.to_string()),
+            "filter" => self.filter = Some(value.to_string()),
+            "scanlines" => self.scanlines = parse_switch(name, value)?,
+            "grid" => self.grid = parse_switch(name, value)?,
+            "screenshot-dir" => self.screenshot_dir = Some(value.to_string()),
+            "record-gif" => self.record_gif = Some(PathBuf::from(value)),
+            "dump-frames" => self.dump_frames = Some(PathBuf::from(value)),
+            "frame-format" => self.frame_format = Some(value.to_string()),
+            "record-audio" => self.record_audio = Some(PathBuf::from(value)),
+            "tone-hz" => self.tone_hz = Some(value.to_string()),
+            "volume" => self.volume = Some(value.to_string()),
+            "waveform" => self.waveform = Some(value.to_string()),
+            "layout" => self.layout = Some(value.to_string()),
+            "pad-deadzone" => self.pad_deadzone = Some(value.to_string()),
+            "key-wait" => self.key_wait = Some(value.to_string()),
+            "keypad" => self.keypad = parse_switch(name, value)?,
+            "turbo-rate" => self.turbo_rate = Some(value.to_string()),
+            "gdb" => self.gdb = Some(value.to_string()),
+            "dap" => self.dap = Some(value.to_string()),
+            "trace" => self.trace = Some(PathBuf::from(value)),
+            "trace-pc" => self.trace_pc = Some(value.to_string()),
+            "trace-only" => self.trace_only = Some(value.to_string()),
+            "trace-last" => self.trace_last = Some(value.to_string()),
+            "crash-dir" => self.crash_dir = Some(value.to_string()),
+            "load-state" => self.load_state = Some(PathBuf::from(value)),
+            "debug-window" => self.debug_window = parse_switch(name, value)?,
+            "symbols" => self.symbols = Some(PathBuf::from(value)),
+            "headless" => self.headless = Some(value.to_string()),
+            _ if name.starts_with("pad-") => self
+                .pad_bindings
+                .push((name.to_string(), value.to_string())),
+            _ if name.starts_with("key-")
+                || name.starts_with("hotkey-")
+                || name.starts_with("turbo-") =>
+            {
+                self.key_bindings
+                    .push((name.to_string(), value.to_string()))
+            }
+            _ => return UnknownOption { name }.fail(),
+        }
+
+        Ok(())
+    }
+
+    fn load_file(&mut self, path: &str) -> Result<(), ConfigError> {
+        let text = fs::read_to_string(path).context(ReadConfig { path })?;
+        let canonical = fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path));
+        if self.loading.contains(&canonical) {
+            return IncludeLoop { path }.fail();
+        }
+        self.loading.push(canonical);
+        let result = self.load_lines(path, &text);
+        self.loading.pop();
+        result
+    }
+
+    fn load_lines(&mut self, path: &str, text: &str) -> Result<(), ConfigError> {
+        let rom_name = Path::new(&self.rom_path)
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string());
+        let mut in_other_rom = false;
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if line.starts_with('[') && line.ends_with(']') {
+                let section = line[1..line.len() - 1].trim();
+                in_other_rom = rom_name.as_deref() != Some(section);
+                continue;
+            }
+            if in_other_rom {
+                continue;
+            }
+            let mut parts = line.splitn(2, '=');
+            let name = parts.next().unwrap().trim();
+            let value = parts
+                .next()
+                .context(BadConfigLine { path, line: i + 1 })?
+                .trim();
+            self.set(name, value)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The CHIP-8 key named by a single hex digit in option `name`.
+fn parse_key_digit(name: &str, digit: &str) -> Result<usize, ConfigError> {
+    usize::from_str_radix(digit, 16)
+        .ok()
+        .filter(|key| digit.len() == 1 && *key < 16)
+        .context(UnknownOption { name })
+}
+
+fn parse_switch(name: &str, value: &str) -> Result<bool, ConfigError> {
+    match value {
+        "on" | "true" | "yes" => Ok(true),
+        "off" | "false" | "no" => Ok(false),
+        _ => InvalidValue { name, value }.fail(),
+    }
+}
+
+pub fn usage() -> String {
+    let lines = [
+        "Usage: rust_chip8 [options] rom_file",
+        "       rust_chip8 [options] --dap ADDRESS [rom_file]",
+        "       rust_chip8 [options] --load-state FILE [rom_file]",
+        "",
+        "Options:",
+        "  --config FILE     read options from FILE, one `option = value` per line",
+        &format!(
+            "  --theme NAME      color theme: {}",
+            Palette::theme_names().join(", ")
+        ),
+        "  --fg RRGGBB       foreground color",
+        "  --bg RRGGBB       background color",
+        "  --display MODE    raw, fade (pixels fade out) or blend (last two frames mixed)",
+        "  --decay AMOUNT    brightness kept per frame in fade mode, 0 to 1 (default 0.6)",
+        "  --filter NAME     upscaling filter: none, scale2x or scale3x",
+        "  --scanlines on    darken the bottom line of every pixel",
+        "  --grid on         draw a grid between pixels",
+        "  --screenshot-dir DIR  where F12 screenshots and F9 recordings go (default screenshots)",
+        "  --record-gif FILE     record the display to an animated GIF",
+        "  --dump-frames DIR     write every displayed frame to DIR as numbered images",
+        "  --frame-format FMT    format of dumped frames: png or ppm",
+        "  --record-audio FILE   record the beeper to a WAV file",
+        "  --tone-hz HZ          beeper pitch (default 240)",
+        "  --volume LEVEL        beeper volume, 0 to 1 (default 0.25)",
+        "  --waveform NAME       beeper waveform: square, triangle, sine or noise",
+        "  --layout NAME         keypad layout: qwerty, azerty, dvorak or numpad",
+        "  --key-X KEY           bind CHIP-8 key X (0 to f) to a host key, e.g. --key-5 Up",
+        &format!(
+            "  --hotkey-NAME KEY     bind a hotkey ({}) to a host key",
+            KeyMap::hotkey_names().join(", ")
+        ),
+        "  --turbo-X KEY         autofire CHIP-8 key X while a host key is held",
+        "  --turbo-rate N        autofire presses per second, 1 to 30 (default 10)",
+        "  --pad-INPUT X         bind a controller button (a, dpup, ...) or axis direction",
+        "                        (leftx-, righty+, ...) to CHIP-8 key X",
+        "  --pad-deadzone N      analog stick deadzone, 0 to 32767 (default 8000)",
+        "  --keypad on           show a clickable keypad next to the display (F1 toggles)",
+        "  --key-wait MODE       when Fx0A takes a key: release (default) or press",
+        "  --gdb ADDRESS         wait for GDB to connect on ADDRESS, e.g. 127.0.0.1:1234",
+        "  --dap ADDRESS         wait for a Debug Adapter Protocol client on ADDRESS",
+        "  --trace FILE          log every executed instruction to FILE",
+        "  --trace-pc FROM-TO    only trace instructions at these hex addresses, e.g. 200-2ff",
+        "  --trace-only LIST     only trace these instructions, e.g. DRW,CALL,RET",
+        "  --trace-last N        only write the last N instructions, when the ROM hits an",
+        "                        invalid instruction or stops in the debugger",
+        "  --crash-dir DIR       where crash reports go when a ROM faults (default crashes)",
+        "  --load-state FILE     start from a save state, such as one from a crash report",
+        "  --debug-window on     show registers, disassembly and memory in a second window",
+        "                        (F3 toggles)",
+        "  --symbols FILE        labels for the debuggers, disassembly and traces (default",
+        "                        the ROM's name with .sym, if there is one)",
+        "  --headless FRAMES     run FRAMES frames (60 a second) as fast as possible without a",
+        "                        window or sound device, e.g. with --record-audio",
+    ];
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sdl2::pixels::Color;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn rom_path_is_required() {
+        match Config::from_args(&args(&["--theme", "amber"])) {
+            Err(ConfigError::MissingRom) => {}
+            other => panic!("Expected MissingRom, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn colors_override_theme() {
+        let config =
+            Config::from_args(&args(&["--fg", "102030", "--theme", "lcd", "pong.ch8"])).unwrap();
+        let palette = config.palette().unwrap();
+        assert_eq!(config.rom_path, "pong.ch8");
+        assert_eq!(palette.colors[1], Color::RGB(0x10, 0x20, 0x30));
+        assert_eq!(
+            palette.colors[0],
+            Palette::from_theme("lcd").unwrap().colors[0]
+        );
+    }
+
+    #[test]
+    fn fade_display_mode_with_decay() {
+        let config =
+            Config::from_args(&args(&["--display", "fade", "--decay", "0.25", "pong.ch8"]))
+                .unwrap();
+        assert_eq!(
+            config.display_mode().unwrap(),
+            DisplayMode::Fade { decay: 0.25 }
+        );
+    }
+
+    #[test]
+    fn filter_options() {
+        let config = Config::from_args(&args(&[
+            "--filter",
+            "scale3x",
+            "--scanlines",
+            "on",
+            "pong.ch8",
+        ]))
+        .unwrap();
+        let filters = config.filters().unwrap();
+        assert_eq!(filters.upscaler, Upscaler::Scale3x);
+        assert!(filters.scanlines);
+        assert!(!filters.grid);
+    }
+
+    #[test]
+    fn tone_settings() {
+        let config = Config::from_args(&args(&[
+            "--waveform",
+            "sine",
+            "--tone-hz",
+            "440",
+            "pong.ch8",
+        ]))
+        .unwrap();
+        let settings = config.tone_settings().unwrap();
+        assert_eq!(settings.waveform, Waveform::Sine);
+        assert_eq!(settings.frequency, 440.0);
+        assert_eq!(settings.volume, ToneSettings::default().volume);
+    }
+
+    #[test]
+    fn volume_out_of_range_is_rejected() {
+        assert!(Config::from_args(&args(&["--volume", "2", "pong.ch8"])).is_err());
+    }
+
+    #[test]
+    fn rom_sections_only_apply_to_their_rom() {
+        let path = std::env::temp_dir().join("rust_chip8_rom_sections.conf");
+        fs::write(
+            &path,
+            "theme = amber\n[pong.ch8]\ntheme = lcd\n[tetris.ch8]\ntheme = green\n",
+        )
+        .unwrap();
+        let config_path = path.to_string_lossy().to_string();
+
+        let pong = Config::from_args(&args(&["--config", &config_path, "roms/pong.ch8"])).unwrap();
+        let other = Config::from_args(&args(&["--config", &config_path, "brix.ch8"])).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(pong.palette().unwrap().name, "lcd");
+        assert_eq!(other.palette().unwrap().name, "amber");
+    }
+
+    #[test]
+    fn config_files_cannot_include_themselves() {
+        let dir = std::env::temp_dir();
+        let first = dir.join("rust_chip8_include_first.conf");
+        let second = dir.join("rust_chip8_include_second.conf");
+        fs::write(
+            &first,
+            format!("theme = lcd\nconfig = {}\n", second.display()),
+        )
+        .unwrap();
+        fs::write(&second, format!("config = {}\n", first.display())).unwrap();
+        let first_path = first.to_string_lossy().to_string();
+        let second_path = second.to_string_lossy().to_string();
+
+        let looped = Config::from_args(&args(&["--config", &first_path, "pong.ch8"]));
+        fs::write(&second, "theme = green\n").unwrap();
+        let twice = Config::from_args(&args(&[
+            "--config",
+            &second_path,
+            "--config",
+            &second_path,
+            "pong.ch8",
+        ]));
+        fs::remove_file(&first).unwrap();
+        fs::remove_file(&second).unwrap();
+
+        match looped {
+            Err(ConfigError::IncludeLoop { path }) => assert_eq!(path, first_path),
+            other => panic!("expected an include loop, got {:?}", other),
+        }
+        assert_eq!(twice.unwrap().palette().unwrap().name, "green");
+    }
+
+    #[test]
+    fn key_bindings_apply_over_layout() {
+        let config = Config::from_args(&args(&[
+            "--layout",
+            "dvorak",
+            "--key-5",
+            "Up",
+            "--hotkey-screenshot",
+            "P",
+            "pong.ch8",
+        ]))
+        .unwrap();
+        let keymap = config.keymap().unwrap();
+        assert_eq!(keymap.chip8_key(Keycode::Up), Some(0x5));
+        assert_eq!(keymap.chip8_key(Keycode::Comma), None);
+        assert_eq!(keymap.chip8_key(Keycode::O), Some(0x8));
+        assert_eq!(keymap.hotkey(Keycode::P), Some(Hotkey::Screenshot));
+    }
+
+    #[test]
+    fn pad_bindings() {
+        let config = Config::from_args(&args(&[
+            "--pad-a",
+            "c",
+            "--pad-deadzone",
+            "1000",
+            "pong.ch8",
+        ]))
+        .unwrap();
+        let map = config.pad_map().unwrap();
+        let mut expected = PadMap::default();
+        expected.bind(PadInput::from_name("a").unwrap(), 0xc);
+        expected.deadzone = 1000;
+        assert_eq!(map, expected);
+
+        for value in &["-1", "-32768", "32768"] {
+            match Config::from_args(&args(&["--pad-deadzone", value, "pong.ch8"])) {
+                Err(ConfigError::InvalidValue { name, .. }) => assert_eq!(name, "pad-deadzone"),
+                other => panic!("Expected InvalidValue, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn turbo_bindings() {
+        let config = Config::from_args(&args(&[
+            "--turbo-5",
+            "Space",
+            "--turbo-rate",
+            "15",
+            "pong.ch8",
+        ]))
+        .unwrap();
+        assert_eq!(
+            config.keymap().unwrap().turbo_key(Keycode::Space),
+            Some(0x5)
+        );
+        assert_eq!(config.turbo_rate().unwrap(), 15);
+
+        assert!(Config::from_args(&args(&["--turbo-rate", "0", "pong.ch8"])).is_err());
+    }
+
+    #[test]
+    fn gdb_address() {
+        let config = Config::from_args(&args(&["--gdb", "127.0.0.1:1234", "pong.ch8"])).unwrap();
+        assert_eq!(
+            config.gdb_address().unwrap(),
+            Some("127.0.0.1:1234".parse().unwrap())
+        );
+        assert!(Config::from_args(&args(&["--gdb", "1234", "pong.ch8"])).is_err());
+    }
+
+    #[test]
+    fn dap_makes_rom_optional() {
+        let config = Config::from_args(&args(&["--dap", "127.0.0.1:4711"])).unwrap();
+        assert_eq!(config.rom_path, "");
+        assert!(config.dap_address().unwrap().is_some());
+
+        match Config::from_args(&args(&[
+            "--dap",
+            "127.0.0.1:4711",
+            "--gdb",
+            "127.0.0.1:1234",
+        ])) {
+            Err(ConfigError::ConflictingOptions { .. }) => {}
+            other => panic!("Expected ConflictingOptions, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn key_wait_mode() {
+        let config = Config::from_args(&args(&["pong.ch8"])).unwrap();
+        assert_eq!(config.key_wait_mode().unwrap(), KeyWaitMode::Release);
+
+        let config = Config::from_args(&args(&["--key-wait", "press", "pong.ch8"])).unwrap();
+        assert_eq!(config.key_wait_mode().unwrap(), KeyWaitMode::Press);
+    }
+
+    #[test]
+    fn bad_key_digit_is_rejected() {
+        match Config::from_args(&args(&["--key-g", "W", "pong.ch8"])) {
+            Err(ConfigError::UnknownOption { name }) => assert_eq!(name, "key-g"),
+            other => panic!("Expected UnknownOption, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn trace_options() {
+        let config = Config::from_args(&args(&[
+            "--trace",
+            "trace.log",
+            "--trace-pc",
+            "200-2FF",
+            "--trace-only",
+            "drw, call",
+            "--trace-last",
+            "500",
+            "pong.ch8",
+        ]))
+        .unwrap();
+        assert_eq!(config.trace, Some(PathBuf::from("trace.log")));
+        assert_eq!(
+            config.trace_filter().unwrap(),
+            TraceFilter {
+                pc_range: Some((0x200, 0x2ff)),
+                mnemonics: vec!["DRW".to_string(), "CALL".to_string()],
+            }
+        );
+        assert_eq!(config.trace_last().unwrap(), Some(500));
+
+        for (name, value) in &[
+            ("trace-pc", "2ff-200"),
+            ("trace-only", "MOV"),
+            ("trace-last", "0"),
+        ] {
+            let option = format!("--{}", name);
+            match Config::from_args(&args(&[&option, value, "pong.ch8"])) {
+                Err(ConfigError::InvalidValue { name: invalid, .. }) => assert_eq!(&invalid, name),
+                other => panic!("Expected InvalidValue, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn save_state_replaces_rom() {
+        let config = Config::from_args(&args(&["--load-state", "crash.state"])).unwrap();
+        assert_eq!(config.rom_path, "");
+        assert_eq!(config.load_state, Some(PathBuf::from("crash.state")));
+        assert_eq!(config.crash_dir(), PathBuf::from("crashes"));
+    }
+
+    #[test]
+    fn symbols_default_to_the_rom_name() {
+        let config = Config::from_args(&args(&["--symbols", "pong.labels", "pong.ch8"])).unwrap();
+        assert_eq!(config.symbols_path(), Some(PathBuf::from("pong.labels")));
+        let config = Config::from_args(&args(&["no/such/dir/pong.ch8"])).unwrap();
+        assert_eq!(config.symbols_path(), None);
+    }
+
+    #[test]
+    fn headless_runs_a_number_of_frames() {
+        let config = Config::from_args(&args(&["--headless", "600", "pong.ch8"])).unwrap();
+        assert_eq!(config.headless_frames().unwrap(), Some(600));
+        assert!(Config::from_args(&args(&["--headless", "0", "pong.ch8"])).is_err());
+        match Config::from_args(&args(&["--headless", "60", "--gdb", "127.0.0.1:1234", "a"])) {
+            Err(ConfigError::ConflictingOptions { .. }) => {}
+            other => panic!("Expected ConflictingOptions, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn unknown_theme_is_rejected() {
+        match Config::from_args(&args(&["--theme", "sepia", "pong.ch8"])) {
+            Err(ConfigError::InvalidValue { name, .. }) => assert_eq!(name, "theme"),
+            other => panic!("Expected InvalidValue, got {:?}", other),
+        }
+    }
+}
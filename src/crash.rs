@@ -0,0 +1,177 @@
+use crate::debugger::*;
+use crate::graphics::*;
+use crate::screenshot::*;
+use crate::state::*;
+use crate::types::*;
+use crate::util::*;
+use snafu::{ResultExt, Snafu};
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Instructions kept for the crash report.
+pub const CRASH_TRACE_LENGTH: usize = 64;
+
+/// Something a ROM did that the machine cannot carry on from.
+#[derive(Clone, Copy, Debug, PartialEq, Snafu)]
+pub enum Fault {
+    #[snafu(display("Invalid instruction {:04x}", opcode))]
+    InvalidInstruction { opcode: Word },
+    #[snafu(display("Access to {} bytes at {:#05x} is outside memory", length, address))]
+    MemoryOutOfBounds { address: usize, length: usize },
+    #[snafu(display("Stack overflow"))]
+    StackOverflow,
+    #[snafu(display("Return with an empty stack"))]
+    StackUnderflow,
+}
+
+#[derive(Debug, Snafu)]
+pub enum CrashError {
+    #[snafu(display("Could not create directory {}: {}", path.display(), source))]
+    CreateCrashDirectory {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[snafu(display("Could not write {}: {}", path.display(), source))]
+    WriteReport {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[snafu(display("{}", source))]
+    SaveImage { source: ScreenshotError },
+    #[snafu(display("{}", source))]
+    SaveState { source: StateError },
+}
+
+/// Writes a crash report into `directory`: a text file describing the fault and the
+/// machine, a PNG of the display (`image`, RGBA) and a save state that `--load-state`
+/// can load. Returns the path of the text file.
+pub fn write_crash_dump(
+    directory: &Path,
+    fault: Fault,
+    state: &MachineState,
+    recent: &[String],
+    image: &[u8],
+) -> Result<PathBuf, CrashError> {
+    fs::create_dir_all(directory).context(CreateCrashDirectory { path: directory })?;
+    let name = format!("crash-{}", timestamp());
+    let state_path = directory.join(format!("{}.state", name));
+    state.save(&state_path).context(SaveState)?;
+    save_png(
+        &directory.join(format!("{}.png", name)),
+        image,
+        WIDTH,
+        HEIGHT,
+    )
+    .context(SaveImage)?;
+    let path = directory.join(format!("{}.txt", name));
+    let report = crash_report(fault, state, recent, &state_path);
+    fs::write(&path, report).context(WriteReport { path: &path })?;
+    Ok(path)
+}
+
+fn crash_report(
+    fault: Fault,
+    state: &MachineState,
+    recent: &[String],
+    state_path: &Path,
+) -> String {
+    let mut report = String::new();
+    let pc = state.register(Register::Pc);
+    // Writing to a String cannot fail.
+    let _ = writeln!(report, "{} at {:03x}", fault, pc);
+    let _ = writeln!(
+        report,
+        "Load {} with --load-state to inspect it in a debugger.",
+        state_path.display()
+    );
+
+    let _ = writeln!(report, "\nRegisters");
+    let registers: Vec<String> = Register::all()
+        .into_iter()
+        .map(|register| {
+            format!(
+                "{}={:0width$x}",
+                register.name().to_uppercase(),
+                state.register(register),
+                width = register.size() * 2
+            )
+        })
+        .collect();
+    for line in registers.chunks(8) {
+        let _ = writeln!(report, "  {}", line.join(" "));
+    }
+
+    let _ = writeln!(report, "\nCall stack");
+    let _ = writeln!(report, "  #0  {:03x}", pc);
+    for (depth, address) in state.call_stack().iter().enumerate() {
+        let _ = writeln!(
+            report,
+            "  #{:<2} {:03x}  called from {:03x}",
+            depth + 1,
+            address,
+            address.wrapping_sub(2)
+        );
+    }
+
+    let _ = writeln!(report, "\nLast {} instructions", recent.len());
+    for line in recent {
+        let _ = writeln!(report, "{}", line.trim_end());
+    }
+
+    let _ = writeln!(report, "\nMemory");
+    for (row, bytes) in state.memory.chunks(16).enumerate() {
+        // Runs of empty memory are left out.
+        if bytes.iter().all(|&byte| byte == 0) {
+            continue;
+        }
+        let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+        let _ = writeln!(report, "  {:03x}  {}", row * 16, hex.join(" "));
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report_describes_the_machine() {
+        let mut registers = [0; REGISTER_COUNT];
+        registers[Register::Pc.index()] = 0x2f4;
+        registers[Register::Sp.index()] = 1;
+        registers[Register::I.index()] = 0xffe;
+        let mut stack = vec![0; STACK_SIZE];
+        stack[0] = 0x206;
+        let mut memory = vec![0; MEMORY_SIZE];
+        memory[0x2f4] = 0xd1;
+        memory[0x2f5] = 0x25;
+        let state = MachineState {
+            registers,
+            stack,
+            memory,
+            vram: [[0; WIDTH]; HEIGHT],
+        };
+        let fault = Fault::MemoryOutOfBounds {
+            address: 0xffe,
+            length: 5,
+        };
+        let recent = vec!["         1  2f4  d125  DRW V1, V2, 5".to_string()];
+        let report = crash_report(fault, &state, &recent, Path::new("crash.state"));
+        let lines: Vec<&str> = report.lines().collect();
+
+        assert_eq!(
+            lines[0],
+            "Access to 5 bytes at 0xffe is outside memory at 2f4"
+        );
+        assert!(lines.contains(&"  I=0ffe PC=02f4 SP=0001 DT=00 ST=00"));
+        assert!(lines.contains(&"  #0  2f4"));
+        assert!(lines.contains(&"  #1  206  called from 204"));
+        assert!(lines.contains(&"         1  2f4  d125  DRW V1, V2, 5"));
+        assert!(lines.contains(&"  2f0  00 00 00 00 d1 25 00 00 00 00 00 00 00 00 00 00"));
+        assert_eq!(
+            lines.iter().filter(|line| line.starts_with("  2")).count(),
+            1
+        );
+    }
+}
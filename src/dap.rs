@@ -0,0 +1,886 @@
+use crate::condition::*;
+use crate::debugger::*;
+use crate::server::*;
+use crate::symbols::*;
+use crate::types::*;
+use serde_json::{json, Value};
+use std::convert::TryFrom;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+const THREAD_ID: i64 = 1;
+const REGISTERS_SCOPE: i64 = 1;
+const TIMERS_SCOPE: i64 = 2;
+const MEMORY_SCOPE: i64 = 3;
+const MEMORY_ROW: usize = 16;
+
+/// Addresses and conditions of breakpoints set by one kind of request.
+type Breakpoints = Vec<(Word, Option<Condition>)>;
+
+/// Serves the Debug Adapter Protocol to one editor at a time, for example with
+/// `"debugServer": 4711` in a VS Code launch configuration.
+pub struct DapServer {
+    server: DebugServer,
+    session: DapSession,
+}
+
+impl DapServer {
+    pub fn listen(address: SocketAddr) -> Result<DapServer, ServerError> {
+        Ok(DapServer {
+            server: DebugServer::listen(address)?,
+            session: DapSession::new(),
+        })
+    }
+
+    /// Handles any requests that have arrived and reports a stop if the target halted.
+    pub fn poll(&mut self, target: &mut dyn Target) {
+        if !self.server.is_connected() {
+            if let Some(address) = self.server.accept() {
+                println!("Debug adapter client connected from {}", address);
+                target.debugger().attach();
+                self.session = DapSession::new();
+            }
+        }
+        if !self.server.is_connected() {
+            return;
+        }
+        if let Err(err) = self.server.receive() {
+            println!("Debug adapter connection closed: {}", err);
+            self.disconnect(target);
+            return;
+        }
+
+        while let Some(message) = next_message(&mut self.server.buffer) {
+            let request = match message {
+                Ok(request) => request,
+                Err(err) => {
+                    println!("Bad debug adapter message: {}", err);
+                    continue;
+                }
+            };
+            for reply in self.session.handle(&request, target) {
+                self.send(&reply);
+            }
+            if request["command"] == "disconnect" {
+                self.disconnect(target);
+                return;
+            }
+        }
+
+        if let Some(reason) = target.debugger().take_stop() {
+            let event = self.session.stopped_event(reason);
+            self.send(&event);
+        }
+    }
+
+    fn disconnect(&mut self, target: &mut dyn Target) {
+        self.server.disconnect();
+        target.debugger().detach();
+    }
+
+    fn send(&mut self, message: &Value) {
+        if let Err(err) = self.server.send(&encode_message(message)) {
+            println!("Could not write to debug adapter client: {}", err);
+        }
+    }
+}
+
+/// Protocol state for one client.
+struct DapSession {
+    seq: i64,
+    stop_on_entry: bool,
+    /// Each request replaces only the breakpoints of its own kind.
+    instruction_breakpoints: Breakpoints,
+    function_breakpoints: Breakpoints,
+}
+
+impl DapSession {
+    fn new() -> Self {
+        DapSession {
+            seq: 0,
+            stop_on_entry: false,
+            instruction_breakpoints: Vec::new(),
+            function_breakpoints: Vec::new(),
+        }
+    }
+
+    /// Answers a request, returning its response followed by any events.
+    fn handle(&mut self, request: &Value, target: &mut dyn Target) -> Vec<Value> {
+        let command = request["command"].as_str().unwrap_or_default();
+        let arguments = &request["arguments"];
+        let mut events = Vec::new();
+        let body = match command {
+            "initialize" => {
+                events.push(self.event("initialized", json!({})));
+                Ok(json!({
+                    "supportsConfigurationDoneRequest": true,
+                    "supportsInstructionBreakpoints": true,
+                    "supportsConditionalBreakpoints": true,
+                    "supportsFunctionBreakpoints": true,
+                    "supportsDataBreakpoints": true,
+                    "supportsReadMemoryRequest": true,
+                    "supportsWriteMemoryRequest": true,
+                    "supportsSetVariable": true,
+                    "supportsStepBack": true,
+                }))
+            }
+            "launch" | "attach" => self.launch(arguments, target),
+            "configurationDone" => {
+                if self.stop_on_entry {
+                    events.push(self.event(
+                        "stopped",
+                        json!({ "reason": "entry", "threadId": THREAD_ID, "allThreadsStopped": true }),
+                    ));
+                } else {
+                    target.debugger().resume();
+                }
+                Ok(json!({}))
+            }
+            "setBreakpoints" => Ok(source_breakpoints(arguments)),
+            "setInstructionBreakpoints" => {
+                let (body, breakpoints) = instruction_breakpoints(arguments);
+                self.instruction_breakpoints = breakpoints;
+                self.set_breakpoints(target);
+                Ok(body)
+            }
+            "setFunctionBreakpoints" => {
+                let (body, breakpoints) = function_breakpoints(arguments, target.symbols());
+                self.function_breakpoints = breakpoints;
+                self.set_breakpoints(target);
+                Ok(body)
+            }
+            "dataBreakpointInfo" => Ok(data_breakpoint_info(arguments)),
+            "setDataBreakpoints" => Ok(data_breakpoints(arguments, target)),
+            "threads" => Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "CHIP-8" }] })),
+            "stackTrace" => Ok(stack_trace(target)),
+            "scopes" => Ok(json!({
+                "scopes": [
+                    { "name": "Registers", "variablesReference": REGISTERS_SCOPE, "expensive": false },
+                    { "name": "Timers", "variablesReference": TIMERS_SCOPE, "expensive": false },
+                    { "name": "Memory", "variablesReference": MEMORY_SCOPE, "expensive": true },
+                ]
+            })),
+            "variables" => Ok(variables(arguments, target)),
+            "setVariable" => set_variable(arguments, target),
+            "readMemory" => read_memory(arguments, target),
+            "writeMemory" => write_memory(arguments, target),
+            "continue" => {
+                target.debugger().resume();
+                Ok(json!({ "allThreadsContinued": true }))
+            }
+            // Every step runs exactly one instruction.
+            "next" | "stepIn" | "stepOut" => {
+                target.debugger().step();
+                Ok(json!({}))
+            }
+            "stepBack" => {
+                reverse_step(target);
+                Ok(json!({}))
+            }
+            "reverseContinue" => {
+                reverse_continue(target);
+                Ok(json!({}))
+            }
+            "pause" => {
+                target.debugger().halt(StopReason::Interrupt);
+                Ok(json!({}))
+            }
+            "disconnect" => Ok(json!({})),
+            _ => Err(format!("Unsupported request: {}", command)),
+        };
+
+        let mut replies = vec![self.response(request, body)];
+        replies.extend(events);
+        replies
+    }
+
+    fn launch(&mut self, arguments: &Value, target: &mut dyn Target) -> Result<Value, String> {
+        self.stop_on_entry = arguments["stopOnEntry"].as_bool().unwrap_or(false);
+        if let Some(program) = arguments["program"].as_str() {
+            let rom = fs::read(program).map_err(|err| format!("{}: {}", program, err))?;
+            if !target.load_program(&rom) {
+                return Err(format!("{} is too big to load", program));
+            }
+            let path = match arguments["symbols"].as_str() {
+                Some(path) => Some(PathBuf::from(path)),
+                None => symbols_beside(Path::new(program)),
+            };
+            let symbols = match path {
+                Some(path) => SymbolMap::load(&path).map_err(|err| err.to_string())?,
+                None => SymbolMap::default(),
+            };
+            target.set_symbols(symbols);
+        }
+        Ok(json!({}))
+    }
+
+    fn set_breakpoints(&self, target: &mut dyn Target) {
+        let debugger = target.debugger();
+        debugger.clear_breakpoints();
+        for (address, condition) in self
+            .instruction_breakpoints
+            .iter()
+            .chain(&self.function_breakpoints)
+        {
+            debugger.add_breakpoint(*address, condition.clone());
+        }
+    }
+
+    fn stopped_event(&mut self, reason: StopReason) -> Value {
+        let (reason, description) = match reason {
+            StopReason::Breakpoint => ("instruction breakpoint", None),
+            StopReason::Step => ("step", None),
+            StopReason::Interrupt => ("pause", None),
+            StopReason::Watchpoint { kind, address, pc } => {
+                let access = match kind {
+                    WatchKind::Read => "Read of",
+                    WatchKind::Write => "Write to",
+                    WatchKind::Access => "Access to",
+                };
+                let description = format!(
+                    "{} {} by the instruction at {}",
+                    access,
+                    format_address(address),
+                    format_address(pc)
+                );
+                ("data breakpoint", Some(description))
+            }
+            StopReason::Fault(fault) => ("exception", Some(fault.to_string())),
+            StopReason::HistoryStart => (
+                "step",
+                Some("Reached the oldest recorded instruction".to_string()),
+            ),
+        };
+        self.event(
+            "stopped",
+            json!({
+                "reason": reason,
+                "description": description,
+                "threadId": THREAD_ID,
+                "allThreadsStopped": true,
+            }),
+        )
+    }
+
+    fn response(&mut self, request: &Value, body: Result<Value, String>) -> Value {
+        self.seq += 1;
+        let mut response = json!({
+            "seq": self.seq,
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": body.is_ok(),
+        });
+        match body {
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = json!(message),
+        }
+        response
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> Value {
+        self.seq += 1;
+        json!({ "seq": self.seq, "type": "event", "event": event, "body": body })
+    }
+}
+
+/// Nothing maps source lines to addresses, as symbol files only hold labels and there is
+/// no assembler, so line breakpoints are reported as unverified.
+fn source_breakpoints(arguments: &Value) -> Value {
+    let count = arguments["breakpoints"].as_array().map_or(0, Vec::len);
+    let message = "No line information; use a function or instruction breakpoint";
+    let breakpoints: Vec<Value> = (0..count)
+        .map(|_| json!({ "verified": false, "message": message }))
+        .collect();
+    json!({ "breakpoints": breakpoints })
+}
+
+/// The breakpoints at the given addresses, and the body of the response.
+fn instruction_breakpoints(arguments: &Value) -> (Value, Breakpoints) {
+    let mut added = Vec::new();
+    let requested = arguments["breakpoints"]
+        .as_array()
+        .cloned()
+        .unwrap_or_default();
+    let breakpoints: Vec<Value> = requested
+        .iter()
+        .map(|breakpoint| {
+            let offset = breakpoint["offset"].as_i64().unwrap_or(0);
+            let address = breakpoint["instructionReference"]
+                .as_str()
+                .and_then(parse_address)
+                .map(|address| address as i64 + offset)
+                .filter(|address| (0..=0xfff).contains(address))
+                .ok_or_else(|| "Not a CHIP-8 address".to_string());
+            let result = address.and_then(|address| {
+                let condition = parse_condition(breakpoint)?;
+                added.push((address as Word, condition));
+                Ok(address as Word)
+            });
+            match result {
+                Ok(address) => {
+                    json!({ "verified": true, "instructionReference": format_address(address) })
+                }
+                Err(message) => json!({ "verified": false, "message": message }),
+            }
+        })
+        .collect();
+    (json!({ "breakpoints": breakpoints }), added)
+}
+
+/// Breakpoints on labels from the symbol file, or on hex addresses.
+fn function_breakpoints(arguments: &Value, symbols: &SymbolMap) -> (Value, Breakpoints) {
+    let mut added = Vec::new();
+    let requested = arguments["breakpoints"]
+        .as_array()
+        .cloned()
+        .unwrap_or_default();
+    let breakpoints: Vec<Value> = requested
+        .iter()
+        .map(|breakpoint| {
+            let name = breakpoint["name"].as_str().unwrap_or_default();
+            let address = symbols
+                .resolve(name)
+                .ok_or_else(|| format!("No label {}", name));
+            let result = address.and_then(|address| {
+                added.push((address, parse_condition(breakpoint)?));
+                Ok(address)
+            });
+            match result {
+                Ok(address) => {
+                    json!({ "verified": true, "instructionReference": format_address(address) })
+                }
+                Err(message) => json!({ "verified": false, "message": message }),
+            }
+        })
+        .collect();
+    (json!({ "breakpoints": breakpoints }), added)
+}
+
+fn parse_condition(breakpoint: &Value) -> Result<Option<Condition>, String> {
+    match breakpoint["condition"].as_str() {
+        Some(text) if !text.trim().is_empty() => Condition::parse(text)
+            .map(Some)
+            .map_err(|err| err.to_string()),
+        _ => Ok(None),
+    }
+}
+
+/// Memory rows in the variables view, and addresses when the client asks for them,
+/// can be watched. The data ID is `address/length`.
+fn data_breakpoint_info(arguments: &Value) -> Value {
+    let name = arguments["name"].as_str().unwrap_or_default();
+    let length = if arguments["asAddress"].as_bool() == Some(true) {
+        Some(arguments["bytes"].as_u64().unwrap_or(1) as usize)
+    } else if arguments["variablesReference"].as_i64() == Some(MEMORY_SCOPE) {
+        Some(MEMORY_ROW)
+    } else {
+        None
+    };
+    match (parse_address(name), length) {
+        (Some(address), Some(length)) => json!({
+            "dataId": format!("{}/{}", format_address(address as Word), length),
+            "description": format!("{} bytes at {}", length, format_address(address as Word)),
+            "accessTypes": ["read", "write", "readWrite"],
+            "canPersist": false,
+        }),
+        _ => json!({ "dataId": null, "description": "Only memory can be watched" }),
+    }
+}
+
+/// Replaces all watchpoints with the given data breakpoints.
+fn data_breakpoints(arguments: &Value, target: &mut dyn Target) -> Value {
+    target.debugger().clear_watchpoints();
+    let requested = arguments["breakpoints"]
+        .as_array()
+        .cloned()
+        .unwrap_or_default();
+    let breakpoints: Vec<Value> = requested
+        .iter()
+        .map(|breakpoint| {
+            let mut data_id = breakpoint["dataId"].as_str().unwrap_or_default().split('/');
+            let start = data_id.next().and_then(parse_address);
+            let length = data_id.next().and_then(|length| length.parse::<u32>().ok());
+            let kind = match breakpoint["accessType"].as_str() {
+                Some("read") => WatchKind::Read,
+                Some("readWrite") => WatchKind::Access,
+                _ => WatchKind::Write,
+            };
+            let added = match (start, length) {
+                (Some(start), Some(length)) => parse_condition(breakpoint).map(|condition| {
+                    target.debugger().add_watchpoint(Watchpoint {
+                        start: start as Word,
+                        length: length as Word,
+                        kind,
+                        condition,
+                    })
+                }),
+                _ => Err("Unknown data breakpoint".to_string()),
+            };
+            match added {
+                Ok(()) => json!({ "verified": true }),
+                Err(message) => json!({ "verified": false, "message": message }),
+            }
+        })
+        .collect();
+    json!({ "breakpoints": breakpoints })
+}
+
+/// The current instruction, then the return address of every subroutine call, named
+/// by their labels if there is a symbol file.
+fn stack_trace(target: &mut dyn Target) -> Value {
+    let pc = target.read_register(Register::Pc);
+    let frames: Vec<Value> = std::iter::once(pc)
+        .chain(target.call_stack())
+        .enumerate()
+        .map(|(id, address)| {
+            json!({
+                "id": id,
+                "name": target
+                    .symbols()
+                    .locate(address)
+                    .unwrap_or_else(|| format_address(address)),
+                "line": 0,
+                "column": 0,
+                "instructionPointerReference": format_address(address),
+            })
+        })
+        .collect();
+    json!({ "stackFrames": frames, "totalFrames": frames.len() })
+}
+
+fn variables(arguments: &Value, target: &mut dyn Target) -> Value {
+    let variables: Vec<Value> = match arguments["variablesReference"].as_i64() {
+        Some(REGISTERS_SCOPE) => Register::all()
+            .into_iter()
+            .filter(|register| !is_timer(*register))
+            .map(|register| register_variable(register, target))
+            .collect(),
+        Some(TIMERS_SCOPE) => Register::all()
+            .into_iter()
+            .filter(|register| is_timer(*register))
+            .map(|register| register_variable(register, target))
+            .collect(),
+        Some(MEMORY_SCOPE) => target
+            .read_memory(0, usize::MAX)
+            .chunks(MEMORY_ROW)
+            .enumerate()
+            .map(|(row, bytes)| {
+                let address = (row * MEMORY_ROW) as Word;
+                let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+                json!({
+                    "name": format_address(address),
+                    "value": hex.join(" "),
+                    "variablesReference": 0,
+                    "memoryReference": format_address(address),
+                })
+            })
+            .collect(),
+        _ => Vec::new(),
+    };
+    json!({ "variables": variables })
+}
+
+fn is_timer(register: Register) -> bool {
+    register == Register::Dt || register == Register::St
+}
+
+fn register_variable(register: Register, target: &mut dyn Target) -> Value {
+    let value = target.read_register(register);
+    let mut variable = json!({
+        "name": register.name(),
+        "value": format_register(register, value),
+        "variablesReference": 0,
+    });
+    if register == Register::I || register == Register::Pc {
+        variable["memoryReference"] = json!(format_address(value));
+    }
+    variable
+}
+
+fn format_register(register: Register, value: Word) -> String {
+    match register {
+        Register::Dt | Register::St => value.to_string(),
+        _ if register.size() == 1 => format!("{:#04x}", value),
+        _ => format!("{:#06x}", value),
+    }
+}
+
+fn set_variable(arguments: &Value, target: &mut dyn Target) -> Result<Value, String> {
+    let name = arguments["name"].as_str().unwrap_or_default();
+    let register = Register::all()
+        .into_iter()
+        .find(|register| register.name() == name)
+        .ok_or_else(|| format!("{} cannot be changed", name))?;
+    let text = arguments["value"].as_str().unwrap_or_default();
+    let value = parse_number(text)
+        .filter(|&value| value < 1 << (register.size() * 8))
+        .ok_or_else(|| format!("Invalid value for {}: {}", name, text))?;
+    if !target.write_register(register, value as Word) {
+        return Err(format!("Invalid value for {}: {}", name, text));
+    }
+    Ok(json!({ "value": format_register(register, value as Word) }))
+}
+
+fn read_memory(arguments: &Value, target: &mut dyn Target) -> Result<Value, String> {
+    let address = memory_address(arguments)?;
+    let count = arguments["count"].as_u64().unwrap_or(0) as usize;
+    let bytes = target.read_memory(address, count);
+    Ok(json!({
+        "address": format_address(address as Word),
+        "data": encode_base64(&bytes),
+        "unreadableBytes": count - bytes.len(),
+    }))
+}
+
+fn write_memory(arguments: &Value, target: &mut dyn Target) -> Result<Value, String> {
+    let address = memory_address(arguments)?;
+    let data = arguments["data"]
+        .as_str()
+        .and_then(decode_base64)
+        .ok_or("Invalid memory data")?;
+    if !target.write_memory(address, &data) {
+        return Err("Write goes past the end of memory".to_string());
+    }
+    Ok(json!({ "bytesWritten": data.len() }))
+}
+
+fn memory_address(arguments: &Value) -> Result<usize, String> {
+    let base = arguments["memoryReference"]
+        .as_str()
+        .and_then(parse_address)
+        .ok_or("Invalid memory reference")?;
+    let address = (base as i64)
+        .checked_add(arguments["offset"].as_i64().unwrap_or(0))
+        .ok_or("Address outside memory")?;
+    if address < 0 {
+        return Err("Address before the start of memory".to_string());
+    }
+    usize::try_from(address).map_err(|_| "Address outside memory".to_string())
+}
+
+fn format_address(address: Word) -> String {
+    format!("{:#05x}", address)
+}
+
+fn parse_address(text: &str) -> Option<u32> {
+    u32::from_str_radix(text.trim_start_matches("0x"), 16).ok()
+}
+
+/// A hex number with `0x`, or a decimal one.
+fn parse_number(text: &str) -> Option<u32> {
+    match text.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+/// Takes the next complete message off the front of `buffer`. Messages are JSON with a
+/// `Content-Length` header.
+fn next_message(buffer: &mut Vec<u8>) -> Option<Result<Value, serde_json::Error>> {
+    let header_end = buffer.windows(4).position(|window| window == b"\r\n\r\n")?;
+    let header = String::from_utf8_lossy(&buffer[..header_end]).to_string();
+    let length = header
+        .lines()
+        .filter_map(|line| line.strip_prefix("Content-Length:"))
+        .find_map(|value| value.trim().parse::<usize>().ok())
+        .unwrap_or(0);
+    let body_start = header_end + 4;
+    if buffer.len() < body_start + length {
+        return None;
+    }
+    let message: Vec<u8> = buffer.drain(..body_start + length).collect();
+    Some(serde_json::from_slice(&message[body_start..]))
+}
+
+fn encode_message(message: &Value) -> Vec<u8> {
+    let body = message.to_string();
+    format!("Content-Length: {}\r\n\r\n{}", body.len(), body).into_bytes()
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn encode_base64(bytes: &[u8]) -> String {
+    let mut text = String::new();
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (i, &byte)| {
+            group | (byte as u32) << (16 - i * 8)
+        });
+        for i in 0..4 {
+            if i <= chunk.len() {
+                text.push(BASE64[(group >> (18 - i * 6)) as usize & 0x3f] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
+
+fn decode_base64(text: &str) -> Option<Vec<u8>> {
+    let digits: Vec<u32> = text
+        .trim_end_matches('=')
+        .bytes()
+        .map(|digit| BASE64.iter().position(|&b| b == digit).map(|i| i as u32))
+        .collect::<Option<_>>()?;
+    let mut bytes = Vec::new();
+    for chunk in digits.chunks(4) {
+        if chunk.len() == 1 {
+            return None;
+        }
+        let group = chunk
+            .iter()
+            .enumerate()
+            .fold(0, |group, (i, &digit)| group | digit << (18 - i * 6));
+        for i in 0..chunk.len() - 1 {
+            bytes.push((group >> (16 - i * 8)) as u8);
+        }
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::debugger::testing::TestTarget;
+
+    fn request(command: &str, arguments: Value) -> Value {
+        json!({ "seq": 7, "type": "request", "command": command, "arguments": arguments })
+    }
+
+    #[test]
+    fn messages_are_framed_by_length() {
+        let message = json!({ "seq": 1, "command": "threads" });
+        let mut buffer = encode_message(&message);
+        buffer.extend_from_slice(b"Content-Length: 40\r\n\r\n{\"se");
+        assert_eq!(next_message(&mut buffer).unwrap().unwrap(), message);
+        assert!(next_message(&mut buffer).is_none());
+        assert!(buffer.starts_with(b"Content-Length: 40"));
+    }
+
+    #[test]
+    fn initialize_sends_capabilities_then_initialized() {
+        let mut session = DapSession::new();
+        let mut target = TestTarget::new();
+        let replies = session.handle(&request("initialize", json!({})), &mut target);
+        assert_eq!(replies.len(), 2);
+        assert_eq!(replies[0]["request_seq"], 7);
+        assert_eq!(replies[0]["success"], true);
+        assert_eq!(replies[0]["body"]["supportsInstructionBreakpoints"], true);
+        assert_eq!(replies[1]["event"], "initialized");
+    }
+
+    #[test]
+    fn instruction_breakpoints_replace_previous_ones() {
+        let mut session = DapSession::new();
+        let mut target = TestTarget::new();
+        let arguments = json!({ "breakpoints": [
+            { "instructionReference": "0x200", "offset": 4 },
+            { "instructionReference": "banana" },
+        ]});
+        let replies = session.handle(
+            &request("setInstructionBreakpoints", arguments),
+            &mut target,
+        );
+        let breakpoints = &replies[0]["body"]["breakpoints"];
+        assert_eq!(breakpoints[0]["verified"], true);
+        assert_eq!(breakpoints[0]["instructionReference"], "0x204");
+        assert_eq!(breakpoints[1]["verified"], false);
+        assert!(target.debugger.breakpoint_hit(0x204, &target));
+    }
+
+    #[test]
+    fn line_breakpoints_are_unverified() {
+        let mut session = DapSession::new();
+        let mut target = TestTarget::new();
+        let arguments = json!({
+            "source": { "path": "pong.asm" },
+            "breakpoints": [{ "line": 12 }],
+        });
+        let replies = session.handle(&request("setBreakpoints", arguments), &mut target);
+        let breakpoints = &replies[0]["body"]["breakpoints"];
+        assert_eq!(breakpoints.as_array().unwrap().len(), 1);
+        assert_eq!(breakpoints[0]["verified"], false);
+        assert!(breakpoints[0]["message"].as_str().unwrap().contains("function"));
+    }
+
+    #[test]
+    fn breakpoint_conditions_are_checked() {
+        let mut session = DapSession::new();
+        let mut target = TestTarget::new();
+        let arguments = json!({ "breakpoints": [
+            { "instructionReference": "0x204", "condition": "v1 == 2" },
+            { "instructionReference": "0x208", "condition": "v1 = 2" },
+        ]});
+        let replies = session.handle(
+            &request("setInstructionBreakpoints", arguments),
+            &mut target,
+        );
+        let breakpoints = &replies[0]["body"]["breakpoints"];
+        assert_eq!(breakpoints[0]["verified"], true);
+        assert_eq!(breakpoints[1]["verified"], false);
+        assert!(!target.debugger.breakpoint_hit(0x204, &target));
+        target.write_register(Register::V(1), 2);
+        assert!(target.debugger.breakpoint_hit(0x204, &target));
+    }
+
+    #[test]
+    fn memory_rows_can_be_watched() {
+        let mut session = DapSession::new();
+        let mut target = TestTarget::new();
+        let arguments = json!({ "variablesReference": MEMORY_SCOPE, "name": "0x300" });
+        let replies = session.handle(&request("dataBreakpointInfo", arguments), &mut target);
+        let data_id = replies[0]["body"]["dataId"].clone();
+        assert_eq!(data_id, "0x300/16");
+
+        let arguments = json!({ "variablesReference": REGISTERS_SCOPE, "name": "v0" });
+        let replies = session.handle(&request("dataBreakpointInfo", arguments), &mut target);
+        assert_eq!(replies[0]["body"]["dataId"], Value::Null);
+
+        let arguments = json!({ "breakpoints": [{ "dataId": data_id, "accessType": "write" }] });
+        session.handle(&request("setDataBreakpoints", arguments), &mut target);
+        target.debugger.before_instruction(0x20c, false);
+        target.debugger.memory_access(0x30f, 2, true);
+        let hit = target.debugger.watchpoint_hit(&target);
+        target.debugger.after_instruction(hit);
+        let reason = target.debugger.take_stop().unwrap();
+        let event = session.stopped_event(reason);
+        assert_eq!(event["body"]["reason"], "data breakpoint");
+        assert_eq!(
+            event["body"]["description"],
+            "Write to 0x30f by the instruction at 0x20c"
+        );
+    }
+
+    #[test]
+    fn function_breakpoints_use_labels() {
+        let mut session = DapSession::new();
+        let mut target = TestTarget::new();
+        target.symbols = SymbolMap::parse("200 main\n24e draw_player").unwrap();
+        let arguments = json!({ "breakpoints": [{ "instructionReference": "0x204" }] });
+        session.handle(
+            &request("setInstructionBreakpoints", arguments),
+            &mut target,
+        );
+        let arguments =
+            json!({ "breakpoints": [{ "name": "draw_player" }, { "name": "nowhere" }] });
+        let replies = session.handle(&request("setFunctionBreakpoints", arguments), &mut target);
+        let breakpoints = &replies[0]["body"]["breakpoints"];
+        assert_eq!(breakpoints[0]["instructionReference"], "0x24e");
+        assert_eq!(breakpoints[1]["verified"], false);
+        assert!(target.debugger.breakpoint_hit(0x24e, &target));
+        assert!(target.debugger.breakpoint_hit(0x204, &target));
+
+        let arguments = json!({ "breakpoints": [] });
+        session.handle(
+            &request("setInstructionBreakpoints", arguments),
+            &mut target,
+        );
+        assert!(target.debugger.breakpoint_hit(0x24e, &target));
+        assert!(!target.debugger.breakpoint_hit(0x204, &target));
+
+        target.write_register(Register::Pc, 0x252);
+        target.stack = vec![0x20a];
+        let replies = session.handle(
+            &request("stackTrace", json!({ "threadId": 1 })),
+            &mut target,
+        );
+        let frames = &replies[0]["body"]["stackFrames"];
+        assert_eq!(frames[0]["name"], "draw_player+4");
+        assert_eq!(frames[1]["name"], "main+10");
+    }
+
+    #[test]
+    fn stack_trace_lists_return_addresses() {
+        let mut session = DapSession::new();
+        let mut target = TestTarget::new();
+        target.write_register(Register::Pc, 0x30a);
+        target.stack = vec![0x214, 0x202];
+        let replies = session.handle(
+            &request("stackTrace", json!({ "threadId": 1 })),
+            &mut target,
+        );
+        let names: Vec<&str> = replies[0]["body"]["stackFrames"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|frame| frame["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, vec!["0x30a", "0x214", "0x202"]);
+    }
+
+    #[test]
+    fn registers_can_be_viewed_and_set() {
+        let mut session = DapSession::new();
+        let mut target = TestTarget::new();
+        target.write_register(Register::I, 0x2a0);
+        let replies = session.handle(
+            &request(
+                "variables",
+                json!({ "variablesReference": REGISTERS_SCOPE }),
+            ),
+            &mut target,
+        );
+        let variables = replies[0]["body"]["variables"].as_array().unwrap();
+        assert_eq!(variables.len(), 19);
+        assert_eq!(variables[16]["name"], "i");
+        assert_eq!(variables[16]["value"], "0x02a0");
+        assert_eq!(variables[16]["memoryReference"], "0x2a0");
+
+        let arguments =
+            json!({ "variablesReference": REGISTERS_SCOPE, "name": "v3", "value": "0x1f" });
+        let replies = session.handle(&request("setVariable", arguments), &mut target);
+        assert_eq!(replies[0]["body"]["value"], "0x1f");
+        assert_eq!(target.read_register(Register::V(3)), 0x1f);
+
+        let arguments =
+            json!({ "variablesReference": REGISTERS_SCOPE, "name": "v3", "value": "256" });
+        let replies = session.handle(&request("setVariable", arguments), &mut target);
+        assert_eq!(replies[0]["success"], false);
+        let arguments =
+            json!({ "variablesReference": REGISTERS_SCOPE, "name": "sp", "value": "17" });
+        let replies = session.handle(&request("setVariable", arguments), &mut target);
+        assert_eq!(replies[0]["success"], false);
+        assert_eq!(target.read_register(Register::Sp), 0);
+    }
+
+    #[test]
+    fn memory_is_read_and_written_as_base64() {
+        let mut session = DapSession::new();
+        let mut target = TestTarget::new();
+        let arguments = json!({ "memoryReference": "0x200", "offset": 1, "data": "EjRW" });
+        session.handle(&request("writeMemory", arguments), &mut target);
+        assert_eq!(&target.memory[0x201..0x204], &[0x12, 0x34, 0x56]);
+
+        let arguments = json!({ "memoryReference": "0x201", "count": 2 });
+        let replies = session.handle(&request("readMemory", arguments), &mut target);
+        assert_eq!(replies[0]["body"]["data"], "EjQ=");
+        assert_eq!(decode_base64("EjQ="), Some(vec![0x12, 0x34]));
+        assert_eq!(encode_base64(&[0xff]), "/w==");
+
+        let arguments = json!({ "memoryReference": "0xfff", "offset": i64::MAX, "data": "EjRW" });
+        let replies = session.handle(&request("writeMemory", arguments), &mut target);
+        assert_eq!(replies[0]["success"], false);
+        assert_eq!(replies[0]["message"], "Address outside memory");
+    }
+
+    #[test]
+    fn launch_loads_program_and_waits_for_configuration() {
+        let path = std::env::temp_dir().join("dap-launch-test.ch8");
+        fs::write(&path, [0x00, 0xe0]).unwrap();
+        let mut session = DapSession::new();
+        let mut target = TestTarget::new();
+        target.debugger.attach();
+        let arguments = json!({ "program": path.to_str().unwrap(), "stopOnEntry": true });
+        let replies = session.handle(&request("launch", arguments), &mut target);
+        assert_eq!(replies[0]["success"], true);
+        assert_eq!(&target.memory[0x200..0x202], &[0x00, 0xe0]);
+
+        let replies = session.handle(&request("configurationDone", json!({})), &mut target);
+        assert_eq!(replies[1]["event"], "stopped");
+        assert_eq!(replies[1]["body"]["reason"], "entry");
+        assert!(target.debugger.is_halted());
+        fs::remove_file(path).unwrap();
+    }
+}
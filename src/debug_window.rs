@@ -0,0 +1,622 @@
+use crate::debugger::*;
+use crate::font::*;
+use crate::graphics::{mix, BYTES_PER_PIXEL};
+use crate::instruction::decode;
+use crate::memory_editor::*;
+use crate::palette::*;
+use crate::types::*;
+use sdl2::event::Event;
+use sdl2::keyboard::{Keycode, Mod};
+use sdl2::mouse::MouseButton;
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::render::{Canvas, Texture};
+use sdl2::video::Window;
+
+/// Size of the debug window in characters.
+const COLUMNS: usize = 80;
+const ROWS: usize = 46;
+const CELL_WIDTH: usize = GLYPH_WIDTH + 1;
+const CELL_HEIGHT: usize = GLYPH_HEIGHT + 1;
+const IMAGE_WIDTH: usize = COLUMNS * CELL_WIDTH;
+const IMAGE_HEIGHT: usize = ROWS * CELL_HEIGHT;
+/// Window pixels per image pixel.
+const WINDOW_SCALE: u32 = 2;
+
+/// Where the disassembly starts, to the right of the machine state.
+const DISASSEMBLY_COLUMN: usize = 38;
+const DISASSEMBLY_ROWS: usize = 18;
+/// Instructions shown before the one at the program counter.
+const DISASSEMBLY_BEFORE: usize = 8;
+const STACK_ROWS: usize = 8;
+/// The memory view runs across the window below the registers and disassembly.
+const MEMORY_TOP: usize = 20;
+const MEMORY_ROWS: usize = 23;
+const ASCII_COLUMN: usize = 55;
+/// Rows scrolled by a turn of the mouse wheel.
+const WHEEL_ROWS: isize = 3;
+const HELP: &str =
+    "SPACE PAUSE/RUN  CLICK AND TYPE HEX TO EDIT  CTRL+Z UNDO  HOME PC  END I  PGUP PGDN";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Shade {
+    Normal,
+    /// Headings and other labels.
+    Dim,
+    /// The current instruction, drawn inverted.
+    Highlight,
+    /// The bytes at I, in the second plane color.
+    Marked,
+    /// The field being edited, inverted in the second plane color.
+    Cursor,
+}
+
+/// A screen of text, each character with its own shade, and where the editable
+/// fields are in it.
+pub struct TextGrid {
+    cells: Vec<(char, Shade)>,
+    /// Column, row, width and field.
+    fields: Vec<(usize, usize, usize, Field)>,
+}
+
+impl TextGrid {
+    pub fn new() -> Self {
+        TextGrid {
+            cells: vec![(' ', Shade::Normal); COLUMNS * ROWS],
+            fields: Vec::new(),
+        }
+    }
+
+    /// Writes `text` from `column`, `row`, cutting it off at the right edge.
+    pub fn put(&mut self, column: usize, row: usize, text: &str, shade: Shade) {
+        if row >= ROWS {
+            return;
+        }
+        for (i, c) in text.chars().enumerate() {
+            if column + i >= COLUMNS {
+                break;
+            }
+            self.cells[row * COLUMNS + column + i] = (c, shade);
+        }
+    }
+
+    /// Writes `text` and remembers that clicking it selects `field`.
+    fn put_field(&mut self, column: usize, row: usize, text: &str, shade: Shade, field: Field) {
+        self.put(column, row, text, shade);
+        self.fields.push((column, row, text.len(), field));
+    }
+
+    pub fn field_at(&self, column: usize, row: usize) -> Option<Field> {
+        self.fields
+            .iter()
+            .find(|&&(x, y, width, _)| y == row && x <= column && column < x + width)
+            .map(|&(.., field)| field)
+    }
+
+    #[cfg(test)]
+    fn cell(&self, column: usize, row: usize) -> (char, Shade) {
+        self.cells[row * COLUMNS + column]
+    }
+
+    /// The text in `columns` of `row`, without trailing spaces.
+    #[cfg(test)]
+    fn text(&self, row: usize, columns: std::ops::Range<usize>) -> String {
+        let start = row * COLUMNS;
+        let text: String = self.cells[start + columns.start..start + columns.end]
+            .iter()
+            .map(|(c, _)| c)
+            .collect();
+        text.trim_end().to_string()
+    }
+
+    /// Draws the text into an RGBA image, `CELL_WIDTH` by `CELL_HEIGHT` pixels per
+    /// character.
+    fn draw(&self, rgba: &mut [u8], palette: &Palette) {
+        let background = palette.colors[0];
+        let foreground = palette.colors[1];
+        let dim = mix(background, foreground, 0.5);
+        let marked = palette.colors[2];
+        for pixel in rgba.chunks_exact_mut(BYTES_PER_PIXEL) {
+            pixel.copy_from_slice(&[background.r, background.g, background.b, background.a]);
+        }
+        for (i, &(c, shade)) in self.cells.iter().enumerate() {
+            let (x, y) = ((i % COLUMNS) * CELL_WIDTH, (i / COLUMNS) * CELL_HEIGHT);
+            let color = match shade {
+                Shade::Normal => foreground,
+                Shade::Dim => dim,
+                Shade::Highlight => {
+                    fill_cell(rgba, x, y, foreground);
+                    background
+                }
+                Shade::Marked => marked,
+                Shade::Cursor => {
+                    fill_cell(rgba, x, y, marked);
+                    background
+                }
+            };
+            draw_text(rgba, IMAGE_WIDTH, x, y, &c.to_string(), color);
+        }
+    }
+}
+
+fn fill_cell(rgba: &mut [u8], x: usize, y: usize, color: sdl2::pixels::Color) {
+    for row in y..y + CELL_HEIGHT {
+        let start = (row * IMAGE_WIDTH + x) * BYTES_PER_PIXEL;
+        for pixel in rgba[start..start + CELL_WIDTH * BYTES_PER_PIXEL].chunks_exact_mut(4) {
+            pixel.copy_from_slice(&[color.r, color.g, color.b, color.a]);
+        }
+    }
+}
+
+/// Lays out the machine state: registers, timers and the call stack on the left, the
+/// disassembly around the program counter on the right with `*` marking breakpoints,
+/// and the memory view from the editor below them. Labels from the symbol file name
+/// addresses in the call stack and disassembly.
+pub fn layout(debugger: &Debugger, target: &dyn Target, editor: &MemoryEditor) -> TextGrid {
+    let mut grid = TextGrid::new();
+    let register = |register| target.read_register(register);
+    let pc = register(Register::Pc);
+    let symbols = target.symbols();
+    // Call stack lines stop short of the disassembly.
+    let stack_line = |text: String, address: Word| {
+        let text = match symbols.locate(address) {
+            Some(location) => format!("{}  {}", text, location),
+            None => text,
+        };
+        text.chars()
+            .take(DISASSEMBLY_COLUMN - 1)
+            .collect::<String>()
+    };
+
+    grid.put(0, 0, "REGISTERS", Shade::Dim);
+    let status = if debugger.is_halted() {
+        "HALTED"
+    } else {
+        "RUNNING"
+    };
+    grid.put(28, 0, status, Shade::Normal);
+    for v in 0..16 {
+        let (column, row) = ((v % 4) * 7, 1 + v / 4);
+        put_register(&mut grid, column, row, Register::V(v), target, editor);
+    }
+    put_register(&mut grid, 0, 6, Register::I, target, editor);
+    put_register(&mut grid, 8, 6, Register::Pc, target, editor);
+    put_register(&mut grid, 17, 6, Register::Sp, target, editor);
+    put_register(&mut grid, 0, 7, Register::Dt, target, editor);
+    put_register(&mut grid, 7, 7, Register::St, target, editor);
+
+    grid.put(0, 9, "CALL STACK", Shade::Dim);
+    let text = stack_line(format!("#0  {:03X}", pc), pc);
+    grid.put(0, 10, &text, Shade::Normal);
+    let stack = target.call_stack();
+    for (depth, address) in stack.iter().take(STACK_ROWS - 1).enumerate() {
+        let caller = address.wrapping_sub(2);
+        let text = format!("#{:<2} {:03X}  FROM {:03X}", depth + 1, address, caller);
+        grid.put(0, 11 + depth, &stack_line(text, caller), Shade::Normal);
+    }
+    if stack.len() > STACK_ROWS - 1 {
+        grid.put(0, 10 + STACK_ROWS, "...", Shade::Dim);
+    }
+
+    grid.put(DISASSEMBLY_COLUMN, 0, "DISASSEMBLY", Shade::Dim);
+    // Each label gets a line of its own, so lines are dropped from the top to keep the
+    // program counter at the same height.
+    let mut lines: Vec<(Option<Word>, String)> = Vec::new();
+    let mut pc_line = None;
+    let first = pc.saturating_sub(DISASSEMBLY_BEFORE as Word * 2);
+    for address in (first..)
+        .step_by(2)
+        .take(DISASSEMBLY_BEFORE + DISASSEMBLY_ROWS)
+    {
+        let bytes = target.read_memory(address as usize, 2);
+        if bytes.len() < 2 {
+            break;
+        }
+        if let Some(label) = symbols.label(address) {
+            lines.push((None, format!("{}:", label)));
+        }
+        if address == pc {
+            pc_line = Some(lines.len());
+        }
+        let opcode = Word::from_be_bytes([bytes[0], bytes[1]]);
+        let text = if symbols.is_data(address) {
+            format!("DB {:#04x}, {:#04x}", bytes[0], bytes[1])
+        } else {
+            match decode(opcode) {
+                Ok(instruction) => symbols.disassemble(&instruction),
+                Err(_) => "???".to_string(),
+            }
+        };
+        let line = format!("{:03X}  {:04X}  {}", address, opcode, text);
+        lines.push((Some(address), line));
+    }
+    let skip = pc_line.map_or(0, |line| line.saturating_sub(DISASSEMBLY_BEFORE));
+    let shown = lines.iter().skip(skip).take(DISASSEMBLY_ROWS);
+    for (row, (address, line)) in (1..).zip(shown) {
+        let address = match *address {
+            Some(address) => address,
+            None => {
+                grid.put(DISASSEMBLY_COLUMN + 2, row, line, Shade::Dim);
+                continue;
+            }
+        };
+        if debugger.has_breakpoint(address) {
+            grid.put(DISASSEMBLY_COLUMN, row, "*", Shade::Normal);
+        }
+        let shade = if address == pc {
+            Shade::Highlight
+        } else {
+            Shade::Normal
+        };
+        grid.put(DISASSEMBLY_COLUMN + 2, row, line, shade);
+    }
+
+    put_memory(&mut grid, target, editor);
+    grid.put(0, ROWS - 1, HELP, Shade::Dim);
+    grid
+}
+
+fn put_register(
+    grid: &mut TextGrid,
+    column: usize,
+    row: usize,
+    register: Register,
+    target: &dyn Target,
+    editor: &MemoryEditor,
+) {
+    let name = register.name().to_uppercase();
+    grid.put(column, row, &name, Shade::Normal);
+    let value = target.read_register(register);
+    put_value(
+        grid,
+        column + name.len() + 1,
+        row,
+        Field::Register(register),
+        value,
+        Shade::Normal,
+        editor,
+    );
+}
+
+/// Writes a field's value in hex, or what has been typed into it if it is selected.
+fn put_value(
+    grid: &mut TextGrid,
+    column: usize,
+    row: usize,
+    field: Field,
+    value: Word,
+    shade: Shade,
+    editor: &MemoryEditor,
+) {
+    let digits = field.digits();
+    let (text, shade) = if editor.selected() == Some(field) {
+        let text = match editor.typed() {
+            "" => format!("{:0width$X}", value, width = digits),
+            typed => format!("{:_<width$}", typed.to_uppercase(), width = digits),
+        };
+        (text, Shade::Cursor)
+    } else {
+        (format!("{:0width$X}", value, width = digits), shade)
+    };
+    grid.put_field(column, row, &text, shade, field);
+}
+
+/// Rows of sixteen bytes in hex and ASCII, with the instruction at PC highlighted and
+/// the bytes it uses from I marked.
+fn put_memory(grid: &mut TextGrid, target: &dyn Target, editor: &MemoryEditor) {
+    let pc = target.read_register(Register::Pc) as usize;
+    let index = target.read_register(Register::I) as usize;
+    let instruction = target
+        .read_memory(pc, 2)
+        .get(..2)
+        .and_then(|bytes| decode(Word::from_be_bytes([bytes[0], bytes[1]])).ok());
+    let index_end = index + index_length(instruction, target);
+
+    let header = format!("MEMORY  PC {:03X}  I {:03X}", pc, index);
+    grid.put(0, MEMORY_TOP, &header, Shade::Dim);
+    for row in 0..MEMORY_ROWS {
+        let address = editor.top_address() + row * BYTES_PER_ROW;
+        let bytes = target.read_memory(address, BYTES_PER_ROW);
+        if bytes.is_empty() {
+            break;
+        }
+        let y = MEMORY_TOP + 1 + row;
+        grid.put(0, y, &format!("{:03X}", address), Shade::Dim);
+        for (i, &byte) in bytes.iter().enumerate() {
+            let field = Field::Memory(address + i);
+            let shade = if (pc..pc + 2).contains(&(address + i)) {
+                Shade::Highlight
+            } else if (index..index_end).contains(&(address + i)) {
+                Shade::Marked
+            } else {
+                Shade::Normal
+            };
+            // A gap between the two halves of the row.
+            let column = 5 + i * 3 + i / 8;
+            put_value(grid, column, y, field, byte as Word, shade, editor);
+            let shade = if editor.selected() == Some(field) {
+                Shade::Cursor
+            } else {
+                shade
+            };
+            let c = match byte {
+                0x20..=0x7e => byte as char,
+                _ => '.',
+            };
+            grid.put_field(ASCII_COLUMN + i, y, &c.to_string(), shade, field);
+        }
+    }
+}
+
+/// A second window showing the machine state as it runs.
+pub struct DebugWindow {
+    canvas: Canvas<Window>,
+    texture: Texture,
+    image: Vec<u8>,
+    visible: bool,
+    editor: MemoryEditor,
+    /// The last text drawn, to find what a click landed on.
+    grid: TextGrid,
+}
+
+impl DebugWindow {
+    /// Opens the window, shown.
+    pub fn new(sdl_context: &sdl2::Sdl) -> Self {
+        let video = sdl_context.video().unwrap();
+        let window = video
+            .window(
+                "rust-chip-8 debugger",
+                IMAGE_WIDTH as u32 * WINDOW_SCALE,
+                IMAGE_HEIGHT as u32 * WINDOW_SCALE,
+            )
+            .position_centered()
+            .build()
+            .unwrap();
+        let mut canvas = window.into_canvas().build().unwrap();
+        canvas
+            .set_logical_size(IMAGE_WIDTH as u32, IMAGE_HEIGHT as u32)
+            .expect("Could not set logical size");
+        let texture = canvas
+            .create_texture_streaming(
+                PixelFormatEnum::RGBA32,
+                IMAGE_WIDTH as u32,
+                IMAGE_HEIGHT as u32,
+            )
+            .expect("Could not create texture");
+
+        DebugWindow {
+            canvas,
+            texture,
+            image: vec![0; IMAGE_WIDTH * IMAGE_HEIGHT * BYTES_PER_PIXEL],
+            visible: true,
+            editor: MemoryEditor::new(MEMORY_ROWS),
+            grid: TextGrid::new(),
+        }
+    }
+
+    /// The SDL ID of the window, which its events carry.
+    pub fn id(&self) -> u32 {
+        self.canvas.window().id()
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    pub fn toggle(&mut self) {
+        self.visible = !self.visible;
+        if self.visible {
+            self.canvas.window_mut().show();
+        } else {
+            self.canvas.window_mut().hide();
+        }
+    }
+
+    pub fn render(&mut self, debugger: &Debugger, target: &dyn Target, palette: &Palette) {
+        self.grid = layout(debugger, target, &self.editor);
+        self.grid.draw(&mut self.image, palette);
+        self.texture
+            .update(None, &self.image, IMAGE_WIDTH * BYTES_PER_PIXEL)
+            .expect("Could not update texture");
+        self.canvas
+            .copy(&self.texture, None, None)
+            .expect("Could not copy texture");
+        self.canvas.present();
+    }
+
+    /// Handles a key press or click in the window: selecting and editing bytes and
+    /// registers, scrolling the memory view and pausing the machine.
+    pub fn handle_event(&mut self, event: &Event, target: &mut dyn Target) {
+        let editor = &mut self.editor;
+        match *event {
+            Event::MouseButtonDown {
+                mouse_btn: MouseButton::Left,
+                x,
+                y,
+                ..
+            } if x >= 0 && y >= 0 => {
+                let (column, row) = (x as usize / CELL_WIDTH, y as usize / CELL_HEIGHT);
+                editor.select(self.grid.field_at(column, row));
+            }
+            Event::MouseWheel { y, .. } => editor.scroll(-y as isize * WHEEL_ROWS),
+            Event::KeyDown {
+                keycode: Some(keycode),
+                keymod,
+                ..
+            } => match keycode {
+                Keycode::Z if keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD) => {
+                    editor.undo(target);
+                }
+                Keycode::Space => {
+                    let debugger = target.debugger();
+                    if debugger.is_halted() {
+                        debugger.resume();
+                    } else {
+                        debugger.halt(StopReason::Interrupt);
+                    }
+                }
+                Keycode::Return | Keycode::KpEnter => editor.commit(target),
+                Keycode::Backspace => editor.cancel(),
+                Keycode::Left => editor.move_cursor(-1),
+                Keycode::Right => editor.move_cursor(1),
+                Keycode::Up if editor.selected().is_none() => editor.scroll(-1),
+                Keycode::Down if editor.selected().is_none() => editor.scroll(1),
+                Keycode::Up => editor.move_cursor(-(BYTES_PER_ROW as isize)),
+                Keycode::Down => editor.move_cursor(BYTES_PER_ROW as isize),
+                Keycode::PageUp => editor.scroll_page(-1),
+                Keycode::PageDown => editor.scroll_page(1),
+                Keycode::Home => editor.show(target.read_register(Register::Pc) as usize),
+                Keycode::End => editor.show(target.read_register(Register::I) as usize),
+                _ => {
+                    if let Some(digit) = hex_digit(keycode) {
+                        editor.type_digit(digit, target);
+                    }
+                }
+            },
+            _ => {}
+        }
+    }
+}
+
+/// The digit on a number, keypad number or A to F key.
+fn hex_digit(keycode: Keycode) -> Option<u8> {
+    let name = keycode.name();
+    let name = name.trim_start_matches("Keypad ");
+    if name.len() == 1 {
+        u8::from_str_radix(name, 16).ok()
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::debugger::testing::TestTarget;
+    use crate::symbols::SymbolMap;
+
+    #[test]
+    fn shows_registers_stack_and_memory() {
+        let mut target = TestTarget::new();
+        target.write_register(Register::V(10), 0x3c);
+        target.write_register(Register::I, 0x302);
+        target.write_register(Register::Pc, 0x204);
+        target.write_register(Register::Sp, 1);
+        target.stack = vec![0x20a];
+        target.write_memory(0x204, &[0xd1, 0x22]);
+        target.write_memory(0x300, &[0x11, 0x22, 0x33, 0x41, 0x42]);
+        let editor = MemoryEditor::new(MEMORY_ROWS);
+        let grid = layout(&target.debugger, &target, &editor);
+        let left = |row| grid.text(row, 0..DISASSEMBLY_COLUMN);
+
+        assert_eq!(left(3), "V8 00  V9 00  VA 3C  VB 00");
+        assert_eq!(left(6), "I 0302  PC 0204  SP 0001");
+        assert_eq!(left(10), "#0  204");
+        assert_eq!(left(11), "#1  20A  FROM 208");
+
+        let pc_row = MEMORY_TOP + 1;
+        assert_eq!(grid.cell(17, pc_row), ('D', Shade::Highlight));
+        assert_eq!(grid.cell(ASCII_COLUMN + 4, pc_row), ('.', Shade::Highlight));
+        let index_row = MEMORY_TOP + 1 + (0x300 - 0x200) / BYTES_PER_ROW;
+        assert_eq!(
+            grid.text(index_row, 0..COLUMNS),
+            "300  11 22 33 41 42 00 00 00  00 00 00 00 00 00 00 00  .\"3AB..........."
+        );
+        // DRW V1, V2, 2 uses the two bytes at I.
+        assert_eq!(grid.cell(8, index_row), ('2', Shade::Normal));
+        assert_eq!(grid.cell(11, index_row), ('3', Shade::Marked));
+        assert_eq!(grid.cell(14, index_row), ('4', Shade::Marked));
+        assert_eq!(grid.cell(17, index_row), ('4', Shade::Normal));
+    }
+
+    #[test]
+    fn clicks_find_fields() {
+        let mut target = TestTarget::new();
+        target.write_register(Register::V(5), 0xab);
+        let mut editor = MemoryEditor::new(MEMORY_ROWS);
+        let grid = layout(&target.debugger, &target, &editor);
+
+        assert_eq!(
+            grid.field_at(7 + 3, 2),
+            Some(Field::Register(Register::V(5)))
+        );
+        assert_eq!(grid.field_at(7 + 5, 2), None);
+        assert_eq!(grid.field_at(11, 6), Some(Field::Register(Register::Pc)));
+        let row = MEMORY_TOP + 2;
+        assert_eq!(grid.field_at(5, row), Some(Field::Memory(0x210)));
+        assert_eq!(
+            grid.field_at(5 + 9 * 3 + 1, row),
+            Some(Field::Memory(0x219))
+        );
+        assert_eq!(
+            grid.field_at(ASCII_COLUMN + 15, row),
+            Some(Field::Memory(0x21f))
+        );
+
+        target.debugger.halt(StopReason::Interrupt);
+        editor.select(Some(Field::Register(Register::V(5))));
+        editor.type_digit(7, &mut target);
+        let grid = layout(&target.debugger, &target, &editor);
+        assert_eq!(grid.text(2, 7..12), "V5 7_");
+        assert_eq!(grid.cell(10, 2), ('7', Shade::Cursor));
+    }
+
+    #[test]
+    fn disassembly_follows_the_program_counter() {
+        let mut target = TestTarget::new();
+        target.write_memory(0x200, &[0x6a, 0x10, 0xa3, 0x00, 0xd1, 0x25]);
+        target.write_register(Register::Pc, 0x202);
+        target.debugger.add_breakpoint(0x204, None);
+        let grid = layout(&target.debugger, &target, &MemoryEditor::new(MEMORY_ROWS));
+
+        let row_of = |address: &str| {
+            (1..=DISASSEMBLY_ROWS)
+                .find(|&row| {
+                    grid.text(row, DISASSEMBLY_COLUMN..COLUMNS)
+                        .contains(address)
+                })
+                .unwrap()
+        };
+        let pc_row = row_of("202  A300");
+        assert_eq!(pc_row, 1 + DISASSEMBLY_BEFORE);
+        assert_eq!(
+            grid.cell(DISASSEMBLY_COLUMN + 2, pc_row).1,
+            Shade::Highlight
+        );
+        assert!(grid
+            .text(pc_row, DISASSEMBLY_COLUMN..COLUMNS)
+            .ends_with("202  A300  LD I, 0x300"));
+        let breakpoint_row = row_of("204  D125  DRW V1, V2, 5");
+        assert_eq!(grid.cell(DISASSEMBLY_COLUMN, breakpoint_row).0, '*');
+        assert_eq!(grid.cell(DISASSEMBLY_COLUMN, pc_row).0, ' ');
+    }
+
+    #[test]
+    fn labels_name_code_and_data() {
+        let mut target = TestTarget::new();
+        target.symbols = SymbolMap::parse("200 main\n20a draw\n300-303 sprite").unwrap();
+        target.write_memory(0x200, &[0x22, 0x0a, 0xa3, 0x00]);
+        target.write_memory(0x300, &[0x3c, 0x42]);
+        target.write_register(Register::Pc, 0x20c);
+        target.stack = vec![0x202];
+        let editor = MemoryEditor::new(MEMORY_ROWS);
+        let grid = layout(&target.debugger, &target, &editor);
+        let left = |row| grid.text(row, 0..DISASSEMBLY_COLUMN);
+        let right = |row| grid.text(row, DISASSEMBLY_COLUMN + 2..COLUMNS);
+
+        assert_eq!(left(10), "#0  20C  draw+2");
+        assert_eq!(left(11), "#1  202  FROM 200  main");
+        let pc_row = 1 + DISASSEMBLY_BEFORE;
+        assert_eq!(right(pc_row), "20C  0000  SYS");
+        assert_eq!(right(pc_row - 2), "draw:");
+        assert_eq!(right(pc_row - 6), "202  A300  LD I, sprite");
+        assert_eq!(right(pc_row - 7), "200  220A  CALL draw");
+        assert_eq!(right(pc_row - 8), "main:");
+
+        target.write_register(Register::Pc, 0x302);
+        let grid = layout(&target.debugger, &target, &editor);
+        assert_eq!(
+            grid.text(pc_row - 1, DISASSEMBLY_COLUMN + 2..COLUMNS),
+            "300  3C42  DB 0x3c, 0x42"
+        );
+    }
+}
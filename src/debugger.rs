@@ -0,0 +1,561 @@
+use crate::condition::*;
+use crate::crash::Fault;
+use crate::symbols::SymbolMap;
+use crate::types::*;
+use std::collections::HashMap;
+
+/// Registers a debugger can read and write. Debug protocols number them in this order:
+/// V0 to VF, then I, PC, SP, DT and ST.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Register {
+    V(usize),
+    I,
+    Pc,
+    Sp,
+    Dt,
+    St,
+}
+
+pub const REGISTER_COUNT: usize = 21;
+
+impl Register {
+    pub fn from_index(index: usize) -> Option<Register> {
+        match index {
+            0..=15 => Some(Register::V(index)),
+            16 => Some(Register::I),
+            17 => Some(Register::Pc),
+            18 => Some(Register::Sp),
+            19 => Some(Register::Dt),
+            20 => Some(Register::St),
+            _ => None,
+        }
+    }
+
+    /// The inverse of `from_index`.
+    pub fn index(&self) -> usize {
+        match self {
+            Register::V(n) => *n,
+            Register::I => 16,
+            Register::Pc => 17,
+            Register::Sp => 18,
+            Register::Dt => 19,
+            Register::St => 20,
+        }
+    }
+
+    pub fn all() -> Vec<Register> {
+        (0..REGISTER_COUNT)
+            .filter_map(Register::from_index)
+            .collect()
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            Register::V(n) => format!("v{:x}", n),
+            Register::I => "i".to_string(),
+            Register::Pc => "pc".to_string(),
+            Register::Sp => "sp".to_string(),
+            Register::Dt => "dt".to_string(),
+            Register::St => "st".to_string(),
+        }
+    }
+
+    /// Size in bytes.
+    pub fn size(&self) -> usize {
+        match self {
+            Register::I | Register::Pc | Register::Sp => 2,
+            _ => 1,
+        }
+    }
+}
+
+/// Which memory accesses a watchpoint catches.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    Access,
+}
+
+impl WatchKind {
+    fn matches(self, write: bool) -> bool {
+        match self {
+            WatchKind::Read => !write,
+            WatchKind::Write => write,
+            WatchKind::Access => true,
+        }
+    }
+}
+
+/// Stops the machine after an instruction that touches `length` bytes from `start`,
+/// if its condition holds.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Watchpoint {
+    pub start: Word,
+    pub length: Word,
+    pub kind: WatchKind,
+    pub condition: Option<Condition>,
+}
+
+/// Why execution stopped.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StopReason {
+    Breakpoint,
+    Step,
+    Interrupt,
+    /// The instruction at `pc` accessed `address`.
+    Watchpoint {
+        kind: WatchKind,
+        address: Word,
+        pc: Word,
+    },
+    /// Stepping backwards reached the oldest recorded instruction.
+    HistoryStart,
+    /// The instruction at the program counter cannot be run.
+    Fault(Fault),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum RunState {
+    Running,
+    Stepping,
+    Halted,
+}
+
+/// A machine that can be inspected and controlled by a debugger.
+pub trait Target {
+    fn debugger(&mut self) -> &mut Debugger;
+    fn read_register(&self, register: Register) -> Word;
+    /// Returns false without writing anything if the register cannot hold `value`, such
+    /// as a stack pointer past the end of the stack.
+    fn write_register(&mut self, register: Register, value: Word) -> bool;
+    /// Reads up to `length` bytes from `address`, fewer if memory ends first.
+    fn read_memory(&self, address: usize, length: usize) -> Vec<u8>;
+    /// Returns false without writing anything if the bytes do not fit in memory.
+    fn write_memory(&mut self, address: usize, data: &[u8]) -> bool;
+    /// Return addresses of the subroutine calls in progress, innermost first.
+    fn call_stack(&self) -> Vec<Word>;
+    /// Resets the machine and loads a new ROM. Returns false if it does not fit.
+    fn load_program(&mut self, rom: &[u8]) -> bool;
+    /// Undoes the last instruction executed. Returns false if there is no record of it.
+    fn step_back(&mut self) -> bool;
+    /// Labels for the loaded ROM, empty if it has no symbol file.
+    fn symbols(&self) -> &SymbolMap;
+    fn set_symbols(&mut self, symbols: SymbolMap);
+}
+
+/// Undoes one instruction and reports the stop.
+pub fn reverse_step(target: &mut dyn Target) {
+    let reason = if target.step_back() {
+        StopReason::Step
+    } else {
+        StopReason::HistoryStart
+    };
+    target.debugger().stopped(reason);
+}
+
+/// Undoes instructions until the next one to run has a breakpoint, or there are none
+/// left to undo, and reports the stop.
+pub fn reverse_continue(target: &mut dyn Target) {
+    let breakpoints = target.debugger().breakpoints.clone();
+    let reason = loop {
+        if !target.step_back() {
+            break StopReason::HistoryStart;
+        }
+        let pc = target.read_register(Register::Pc);
+        if breakpoint_holds(&breakpoints, pc, target) {
+            break StopReason::Breakpoint;
+        }
+    };
+    target.debugger().stopped(reason);
+}
+
+fn breakpoint_holds(
+    breakpoints: &HashMap<Word, Option<Condition>>,
+    pc: Word,
+    target: &dyn Target,
+) -> bool {
+    match breakpoints.get(&pc) {
+        Some(Some(condition)) => condition.evaluate(target),
+        Some(None) => true,
+        None => false,
+    }
+}
+
+/// Breakpoints, watchpoints and run control, checked by the VM around every
+/// instruction. Conditions need the machine state, so the VM evaluates them with
+/// `breakpoint_hit` and `watchpoint_hit` and passes the results in.
+pub struct Debugger {
+    breakpoints: HashMap<Word, Option<Condition>>,
+    watchpoints: Vec<Watchpoint>,
+    /// Watchpoints touched by the current instruction, with the address touched.
+    touched: Vec<(usize, Word)>,
+    instruction: Word,
+    state: RunState,
+    resuming: bool,
+    stop: Option<StopReason>,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Debugger {
+            breakpoints: HashMap::new(),
+            watchpoints: Vec::new(),
+            touched: Vec::new(),
+            instruction: 0,
+            state: RunState::Running,
+            resuming: false,
+            stop: None,
+        }
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.state == RunState::Halted
+    }
+
+    pub fn add_breakpoint(&mut self, address: Word, condition: Option<Condition>) {
+        self.breakpoints.insert(address, condition);
+    }
+
+    pub fn remove_breakpoint(&mut self, address: Word) {
+        self.breakpoints.remove(&address);
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    pub fn has_breakpoint(&self, address: Word) -> bool {
+        self.breakpoints.contains_key(&address)
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.push(watchpoint);
+    }
+
+    pub fn remove_watchpoint(&mut self, start: Word, length: Word, kind: WatchKind) {
+        self.watchpoints.retain(|watchpoint| {
+            (watchpoint.start, watchpoint.length, watchpoint.kind) != (start, length, kind)
+        });
+    }
+
+    pub fn clear_watchpoints(&mut self) {
+        self.watchpoints.clear();
+    }
+
+    /// Stops the machine for a newly attached debugger, without reporting a stop.
+    pub fn attach(&mut self) {
+        self.state = RunState::Halted;
+        self.stop = None;
+    }
+
+    /// Lets the machine run freely again with no breakpoints or watchpoints.
+    pub fn detach(&mut self) {
+        self.breakpoints.clear();
+        self.watchpoints.clear();
+        self.state = RunState::Running;
+        self.stop = None;
+    }
+
+    pub fn resume(&mut self) {
+        self.state = RunState::Running;
+        self.resuming = true;
+    }
+
+    /// Runs a single instruction, then stops.
+    pub fn step(&mut self) {
+        self.state = RunState::Stepping;
+        self.resuming = true;
+    }
+
+    pub fn halt(&mut self, reason: StopReason) {
+        if self.state != RunState::Halted {
+            self.state = RunState::Halted;
+            self.stop = Some(reason);
+        }
+    }
+
+    /// Whether there is a breakpoint at `pc` whose condition holds.
+    pub fn breakpoint_hit(&self, pc: Word, target: &dyn Target) -> bool {
+        breakpoint_holds(&self.breakpoints, pc, target)
+    }
+
+    /// Called before executing the instruction at `pc`. Returns false if the machine
+    /// should not execute it, because it is halted or hit a breakpoint. The instruction
+    /// execution resumes from never counts as a breakpoint hit.
+    pub fn before_instruction(&mut self, pc: Word, at_breakpoint: bool) -> bool {
+        if self.state == RunState::Halted {
+            return false;
+        }
+        let resuming = self.resuming;
+        self.resuming = false;
+        if !resuming && at_breakpoint {
+            self.halt(StopReason::Breakpoint);
+            return false;
+        }
+        self.instruction = pc;
+        true
+    }
+
+    /// Records the current instruction reading or writing `length` bytes at `address`.
+    pub fn memory_access(&mut self, address: usize, length: usize, write: bool) {
+        for (index, watchpoint) in self.watchpoints.iter().enumerate() {
+            let start = watchpoint.start as usize;
+            let end = start + watchpoint.length as usize;
+            if watchpoint.kind.matches(write) && address < end && start < address + length {
+                self.touched.push((index, address.max(start) as Word));
+            }
+        }
+    }
+
+    /// The first watchpoint the current instruction touched whose condition holds,
+    /// with the address it touched.
+    pub fn watchpoint_hit(&self, target: &dyn Target) -> Option<(WatchKind, Word)> {
+        self.touched.iter().find_map(|&(index, address)| {
+            let watchpoint = &self.watchpoints[index];
+            match &watchpoint.condition {
+                Some(condition) if !condition.evaluate(target) => None,
+                _ => Some((watchpoint.kind, address)),
+            }
+        })
+    }
+
+    pub fn after_instruction(&mut self, watch_hit: Option<(WatchKind, Word)>) {
+        self.touched.clear();
+        if let Some((kind, address)) = watch_hit {
+            self.halt(StopReason::Watchpoint {
+                kind,
+                address,
+                pc: self.instruction,
+            });
+        } else if self.state == RunState::Stepping {
+            self.halt(StopReason::Step);
+        }
+    }
+
+    /// Halts with `reason` to report, even if the machine was already halted, as it is
+    /// when stepping backwards.
+    fn stopped(&mut self, reason: StopReason) {
+        self.state = RunState::Halted;
+        self.stop = Some(reason);
+    }
+
+    /// The reason for a stop not yet reported to the debugger.
+    pub fn take_stop(&mut self) -> Option<StopReason> {
+        self.stop.take()
+    }
+}
+
+/// A plain machine for testing debugger front ends.
+#[cfg(test)]
+pub mod testing {
+    use super::*;
+    use crate::state::STACK_SIZE;
+
+    pub struct TestTarget {
+        pub debugger: Debugger,
+        pub registers: [Word; REGISTER_COUNT],
+        pub memory: Vec<u8>,
+        pub stack: Vec<Word>,
+        /// Earlier register values, most recent last, for stepping back.
+        pub history: Vec<[Word; REGISTER_COUNT]>,
+        pub symbols: SymbolMap,
+    }
+
+    impl TestTarget {
+        pub fn new() -> Self {
+            TestTarget {
+                debugger: Debugger::new(),
+                registers: [0; REGISTER_COUNT],
+                memory: vec![0; 4096],
+                stack: Vec::new(),
+                history: Vec::new(),
+                symbols: SymbolMap::default(),
+            }
+        }
+    }
+
+    impl Target for TestTarget {
+        fn debugger(&mut self) -> &mut Debugger {
+            &mut self.debugger
+        }
+
+        fn read_register(&self, register: Register) -> Word {
+            self.registers[register.index()]
+        }
+
+        fn write_register(&mut self, register: Register, value: Word) -> bool {
+            if register == Register::Sp && value as usize > STACK_SIZE {
+                return false;
+            }
+            self.registers[register.index()] = value;
+            true
+        }
+
+        fn read_memory(&self, address: usize, length: usize) -> Vec<u8> {
+            let start = address.min(self.memory.len());
+            let end = start.saturating_add(length).min(self.memory.len());
+            self.memory[start..end].to_vec()
+        }
+
+        fn write_memory(&mut self, address: usize, data: &[u8]) -> bool {
+            let end = match address.checked_add(data.len()) {
+                Some(end) => end,
+                None => return false,
+            };
+            match self.memory.get_mut(address..end) {
+                Some(memory) => {
+                    memory.copy_from_slice(data);
+                    true
+                }
+                None => false,
+            }
+        }
+
+        fn call_stack(&self) -> Vec<Word> {
+            self.stack.clone()
+        }
+
+        fn load_program(&mut self, rom: &[u8]) -> bool {
+            self.write_memory(0x200, rom)
+        }
+
+        fn step_back(&mut self) -> bool {
+            match self.history.pop() {
+                Some(registers) => {
+                    self.registers = registers;
+                    true
+                }
+                None => false,
+            }
+        }
+
+        fn symbols(&self) -> &SymbolMap {
+            &self.symbols
+        }
+
+        fn set_symbols(&mut self, symbols: SymbolMap) {
+            self.symbols = symbols;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::testing::TestTarget;
+    use super::*;
+
+    #[test]
+    fn breakpoint_halts_before_instruction() {
+        let mut debugger = Debugger::new();
+        assert!(debugger.before_instruction(0x200, false));
+        assert!(!debugger.before_instruction(0x204, true));
+        assert!(debugger.is_halted());
+        assert_eq!(debugger.take_stop(), Some(StopReason::Breakpoint));
+        assert_eq!(debugger.take_stop(), None);
+
+        // Continuing runs the instruction at the breakpoint.
+        debugger.resume();
+        assert!(debugger.before_instruction(0x204, true));
+        debugger.after_instruction(None);
+        assert!(!debugger.before_instruction(0x204, true));
+    }
+
+    #[test]
+    fn step_runs_one_instruction() {
+        let mut debugger = Debugger::new();
+        debugger.attach();
+        assert!(!debugger.before_instruction(0x200, false));
+        assert_eq!(debugger.take_stop(), None);
+
+        debugger.step();
+        assert!(debugger.before_instruction(0x200, false));
+        debugger.after_instruction(None);
+        assert!(!debugger.before_instruction(0x202, false));
+        assert_eq!(debugger.take_stop(), Some(StopReason::Step));
+    }
+
+    #[test]
+    fn conditional_breakpoint() {
+        let mut target = TestTarget::new();
+        let mut debugger = Debugger::new();
+        let condition = Condition::parse("v0 == 3").unwrap();
+        debugger.add_breakpoint(0x210, Some(condition));
+        debugger.add_breakpoint(0x220, None);
+        assert!(!debugger.breakpoint_hit(0x210, &target));
+        assert!(debugger.breakpoint_hit(0x220, &target));
+        assert!(!debugger.breakpoint_hit(0x230, &target));
+
+        target.write_register(Register::V(0), 3);
+        assert!(debugger.breakpoint_hit(0x210, &target));
+    }
+
+    #[test]
+    fn watchpoint_reports_instruction_and_address() {
+        let target = TestTarget::new();
+        let mut debugger = Debugger::new();
+        debugger.add_watchpoint(Watchpoint {
+            start: 0x300,
+            length: 4,
+            kind: WatchKind::Write,
+            condition: None,
+        });
+
+        assert!(debugger.before_instruction(0x208, false));
+        debugger.memory_access(0x2fe, 4, false);
+        assert_eq!(debugger.watchpoint_hit(&target), None);
+        debugger.memory_access(0x2fe, 3, true);
+        let hit = debugger.watchpoint_hit(&target);
+        assert_eq!(hit, Some((WatchKind::Write, 0x300)));
+        debugger.after_instruction(hit);
+        assert_eq!(
+            debugger.take_stop(),
+            Some(StopReason::Watchpoint {
+                kind: WatchKind::Write,
+                address: 0x300,
+                pc: 0x208,
+            })
+        );
+
+        debugger.remove_watchpoint(0x300, 4, WatchKind::Write);
+        debugger.memory_access(0x300, 1, true);
+        assert_eq!(debugger.watchpoint_hit(&target), None);
+    }
+
+    #[test]
+    fn reverse_continue_stops_at_breakpoint() {
+        let mut target = TestTarget::new();
+        for pc in &[0x200, 0x202, 0x204, 0x206] {
+            let mut registers = [0; REGISTER_COUNT];
+            registers[Register::Pc.index()] = *pc;
+            target.history.push(registers);
+        }
+        target.debugger.attach();
+        target.debugger.add_breakpoint(0x202, None);
+
+        reverse_step(&mut target);
+        assert_eq!(target.read_register(Register::Pc), 0x206);
+        assert_eq!(target.debugger.take_stop(), Some(StopReason::Step));
+
+        reverse_continue(&mut target);
+        assert_eq!(target.read_register(Register::Pc), 0x202);
+        assert_eq!(target.debugger.take_stop(), Some(StopReason::Breakpoint));
+
+        reverse_continue(&mut target);
+        assert_eq!(target.read_register(Register::Pc), 0x200);
+        assert_eq!(target.debugger.take_stop(), Some(StopReason::HistoryStart));
+        assert!(target.debugger.is_halted());
+    }
+
+    #[test]
+    fn register_numbering() {
+        assert_eq!(Register::from_index(10), Some(Register::V(10)));
+        assert_eq!(Register::from_index(17), Some(Register::Pc));
+        assert_eq!(Register::from_index(REGISTER_COUNT), None);
+        assert_eq!(Register::all().len(), REGISTER_COUNT);
+        assert_eq!(Register::V(10).name(), "va");
+        for (index, register) in Register::all().into_iter().enumerate() {
+            assert_eq!(register.index(), index);
+        }
+    }
+}
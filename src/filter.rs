@@ -0,0 +1,234 @@
+use crate::graphics::BYTES_PER_PIXEL;
+
+/// Scale used for scanline and grid effects when no upscaler is selected.
+const EFFECT_SCALE: usize = 4;
+
+type Pixel = [u8; BYTES_PER_PIXEL];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Upscaler {
+    None,
+    Scale2x,
+    Scale3x,
+}
+
+/// Post-processing applied to the rendered frame before it is shown.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Filters {
+    pub upscaler: Upscaler,
+    pub scanlines: bool,
+    pub grid: bool,
+}
+
+impl Default for Filters {
+    fn default() -> Self {
+        Filters {
+            upscaler: Upscaler::None,
+            scanlines: false,
+            grid: false,
+        }
+    }
+}
+
+impl Filters {
+    /// How many output pixels each side of a VRAM pixel becomes.
+    pub fn scale(&self) -> usize {
+        match self.upscaler {
+            Upscaler::Scale2x => 2,
+            Upscaler::Scale3x => 3,
+            Upscaler::None if self.scanlines || self.grid => EFFECT_SCALE,
+            Upscaler::None => 1,
+        }
+    }
+
+    /// Filters an RGBA frame of `width` by `height` pixels, returning an RGBA frame
+    /// `scale()` times larger in each direction.
+    pub fn apply(&self, rgba: &[u8], width: usize, height: usize) -> Vec<u8> {
+        let pixels = to_pixels(rgba);
+        let scale = self.scale();
+        let mut scaled = match self.upscaler {
+            Upscaler::None => nearest(&pixels, width, height, scale),
+            Upscaler::Scale2x => scale2x(&pixels, width, height),
+            Upscaler::Scale3x => scale3x(&pixels, width, height),
+        };
+
+        let scaled_width = width * scale;
+        for (i, pixel) in scaled.iter_mut().enumerate() {
+            let (x, y) = (i % scaled_width, i / scaled_width);
+            let edge_row = y % scale == scale - 1;
+            let edge_col = x % scale == scale - 1;
+            if self.scanlines && edge_row {
+                darken(pixel, 0.5);
+            }
+            if self.grid && (edge_row || edge_col) {
+                darken(pixel, 0.75);
+            }
+        }
+
+        scaled.iter().flatten().copied().collect()
+    }
+}
+
+fn to_pixels(rgba: &[u8]) -> Vec<Pixel> {
+    rgba.chunks_exact(BYTES_PER_PIXEL)
+        .map(|p| [p[0], p[1], p[2], p[3]])
+        .collect()
+}
+
+/// Scales an RGBA frame up `scale` times with nearest-neighbor sampling.
+pub fn nearest_rgba(rgba: &[u8], width: usize, height: usize, scale: usize) -> Vec<u8> {
+    nearest(&to_pixels(rgba), width, height, scale).concat()
+}
+
+fn darken(pixel: &mut Pixel, amount: f32) {
+    for channel in pixel.iter_mut().take(3) {
+        *channel = (*channel as f32 * amount) as u8;
+    }
+}
+
+/// Looks up a pixel, clamping coordinates to the edges of the image.
+fn at<T: Copy>(pixels: &[T], width: usize, height: usize, x: isize, y: isize) -> T {
+    let x = x.max(0).min(width as isize - 1) as usize;
+    let y = y.max(0).min(height as isize - 1) as usize;
+    pixels[y * width + x]
+}
+
+pub fn nearest<T: Copy>(pixels: &[T], width: usize, height: usize, scale: usize) -> Vec<T> {
+    let mut out = Vec::with_capacity(pixels.len() * scale * scale);
+    for y in 0..height * scale {
+        for x in 0..width * scale {
+            out.push(pixels[(y / scale) * width + x / scale]);
+        }
+    }
+    out
+}
+
+/// The EPX/Scale2x algorithm: each pixel becomes a 2x2 block, with corners taking a
+/// neighbour's color where two neighbours agree, which smooths diagonal edges.
+pub fn scale2x<T: Copy + PartialEq>(pixels: &[T], width: usize, height: usize) -> Vec<T> {
+    let out_width = width * 2;
+    let mut out = vec![pixels[0]; pixels.len() * 4];
+    for y in 0..height {
+        for x in 0..width {
+            let get =
+                |dx: isize, dy: isize| at(pixels, width, height, x as isize + dx, y as isize + dy);
+            let p = get(0, 0);
+            let (a, b, c, d) = (get(0, -1), get(1, 0), get(-1, 0), get(0, 1));
+
+            let (ox, oy) = (x * 2, y * 2);
+            out[oy * out_width + ox] = if c == a && c != d && a != b { a } else { p };
+            out[oy * out_width + ox + 1] = if a == b && a != c && b != d { b } else { p };
+            out[(oy + 1) * out_width + ox] = if d == c && d != b && c != a { c } else { p };
+            out[(oy + 1) * out_width + ox + 1] = if b == d && b != a && d != c { d } else { p };
+        }
+    }
+    out
+}
+
+/// The Scale3x algorithm, the 3x3 counterpart of `scale2x`.
+pub fn scale3x<T: Copy + PartialEq>(pixels: &[T], width: usize, height: usize) -> Vec<T> {
+    let out_width = width * 3;
+    let mut out = vec![pixels[0]; pixels.len() * 9];
+    for y in 0..height {
+        for x in 0..width {
+            let get =
+                |dx: isize, dy: isize| at(pixels, width, height, x as isize + dx, y as isize + dy);
+            let (a, b, c) = (get(-1, -1), get(0, -1), get(1, -1));
+            let (d, e, f) = (get(-1, 0), get(0, 0), get(1, 0));
+            let (g, h, i) = (get(-1, 1), get(0, 1), get(1, 1));
+
+            let block = [
+                if d == b && b != f && d != h { d } else { e },
+                if (d == b && b != f && d != h && e != c) || (b == f && b != d && f != h && e != a)
+                {
+                    b
+                } else {
+                    e
+                },
+                if b == f && b != d && f != h { f } else { e },
+                if (d == b && b != f && d != h && e != g) || (d == h && d != b && h != f && e != a)
+                {
+                    d
+                } else {
+                    e
+                },
+                e,
+                if (b == f && b != d && f != h && e != i) || (h == f && d != h && b != f && e != c)
+                {
+                    f
+                } else {
+                    e
+                },
+                if d == h && d != b && h != f { d } else { e },
+                if (d == h && d != b && h != f && e != i) || (h == f && d != h && b != f && e != g)
+                {
+                    h
+                } else {
+                    e
+                },
+                if h == f && d != h && b != f { f } else { e },
+            ];
+            for (n, pixel) in block.iter().enumerate() {
+                out[(y * 3 + n / 3) * out_width + x * 3 + n % 3] = *pixel;
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scale2x_fills_corners_where_neighbours_agree() {
+        // 1 0
+        // 0 1
+        let scaled = scale2x(&[1, 0, 0, 1], 2, 2);
+        assert_eq!(
+            scaled,
+            vec![
+                1, 1, 0, 0, //
+                1, 0, 1, 0, //
+                0, 1, 0, 1, //
+                0, 0, 1, 1,
+            ]
+        );
+    }
+
+    #[test]
+    fn scale2x_keeps_flat_areas() {
+        assert_eq!(scale2x(&[7; 4], 2, 2), vec![7; 16]);
+    }
+
+    #[test]
+    fn scale3x_keeps_single_pixel_square() {
+        let scaled = scale3x(&[0, 0, 0, 0, 1, 0, 0, 0, 0], 3, 3);
+        assert_eq!(scaled.iter().filter(|&&p| p == 1).count(), 9);
+        assert_eq!(scaled[4 * 9 + 4], 1);
+    }
+
+    #[test]
+    fn scanlines_darken_last_row_of_each_pixel() {
+        let filters = Filters {
+            upscaler: Upscaler::Scale2x,
+            scanlines: true,
+            grid: false,
+        };
+        let out = filters.apply(&[200, 100, 50, 255], 1, 1);
+        assert_eq!(out.len(), 4 * BYTES_PER_PIXEL);
+        assert_eq!(&out[0..8], &[200, 100, 50, 255, 200, 100, 50, 255]);
+        assert_eq!(&out[8..16], &[100, 50, 25, 255, 100, 50, 25, 255]);
+    }
+
+    #[test]
+    fn effects_without_upscaler_use_nearest() {
+        let filters = Filters {
+            upscaler: Upscaler::None,
+            scanlines: false,
+            grid: true,
+        };
+        assert_eq!(filters.scale(), EFFECT_SCALE);
+        assert_eq!(Filters::default().scale(), 1);
+    }
+}
@@ -0,0 +1,286 @@
+use sdl2::controller::{Axis, Button, GameController};
+use sdl2::event::Event;
+use sdl2::GameControllerSubsystem;
+use std::collections::{HashMap, HashSet};
+
+const DEFAULT_DEADZONE: i16 = 8000;
+
+/// A button, or an analog axis pushed past the deadzone in one direction.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum PadInput {
+    Button(Button),
+    AxisNegative(Axis),
+    AxisPositive(Axis),
+}
+
+const BUTTON_NAMES: [(&str, Button); 15] = [
+    ("a", Button::A),
+    ("b", Button::B),
+    ("x", Button::X),
+    ("y", Button::Y),
+    ("back", Button::Back),
+    ("guide", Button::Guide),
+    ("start", Button::Start),
+    ("leftstick", Button::LeftStick),
+    ("rightstick", Button::RightStick),
+    ("leftshoulder", Button::LeftShoulder),
+    ("rightshoulder", Button::RightShoulder),
+    ("dpup", Button::DPadUp),
+    ("dpdown", Button::DPadDown),
+    ("dpleft", Button::DPadLeft),
+    ("dpright", Button::DPadRight),
+];
+
+const AXIS_NAMES: [(&str, Axis); 6] = [
+    ("leftx", Axis::LeftX),
+    ("lefty", Axis::LeftY),
+    ("rightx", Axis::RightX),
+    ("righty", Axis::RightY),
+    ("lefttrigger", Axis::TriggerLeft),
+    ("righttrigger", Axis::TriggerRight),
+];
+
+impl PadInput {
+    /// Parses SDL's controller mapping names: a button such as `a` or `dpup`, or an
+    /// axis direction such as `leftx-` or `righttrigger+`.
+    pub fn from_name(name: &str) -> Option<PadInput> {
+        if let Some(&(_, button)) = BUTTON_NAMES.iter().find(|(n, _)| *n == name) {
+            return Some(PadInput::Button(button));
+        }
+        let (axis, positive) = match name.strip_suffix('+') {
+            Some(axis) => (axis, true),
+            None => (name.strip_suffix('-')?, false),
+        };
+        let &(_, axis) = AXIS_NAMES.iter().find(|(n, _)| *n == axis)?;
+        if positive {
+            Some(PadInput::AxisPositive(axis))
+        } else {
+            Some(PadInput::AxisNegative(axis))
+        }
+    }
+}
+
+/// Which controller inputs press which CHIP-8 keys.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PadMap {
+    inputs: HashMap<PadInput, usize>,
+    pub deadzone: i16,
+}
+
+impl PadMap {
+    pub fn bind(&mut self, input: PadInput, key: usize) {
+        self.inputs.insert(input, key);
+    }
+}
+
+impl Default for PadMap {
+    /// Directions press 2/4/6/8, the arrows of the CHIP-8 keypad, and the face
+    /// buttons press 5, 0, 1 and 3.
+    fn default() -> Self {
+        let inputs = [
+            (PadInput::Button(Button::DPadUp), 0x2),
+            (PadInput::Button(Button::DPadDown), 0x8),
+            (PadInput::Button(Button::DPadLeft), 0x4),
+            (PadInput::Button(Button::DPadRight), 0x6),
+            (PadInput::AxisNegative(Axis::LeftY), 0x2),
+            (PadInput::AxisPositive(Axis::LeftY), 0x8),
+            (PadInput::AxisNegative(Axis::LeftX), 0x4),
+            (PadInput::AxisPositive(Axis::LeftX), 0x6),
+            (PadInput::Button(Button::A), 0x5),
+            (PadInput::Button(Button::B), 0x0),
+            (PadInput::Button(Button::X), 0x1),
+            (PadInput::Button(Button::Y), 0x3),
+        ];
+        PadMap {
+            inputs: inputs.iter().cloned().collect(),
+            deadzone: DEFAULT_DEADZONE,
+        }
+    }
+}
+
+/// Tracks the inputs held on every controller and turns them into CHIP-8 key
+/// states. Works on plain SDL events, so it can be fed synthetic ones.
+pub struct PadMapper {
+    map: PadMap,
+    held: HashSet<(u32, PadInput)>,
+}
+
+impl PadMapper {
+    pub fn new(map: PadMap) -> Self {
+        PadMapper {
+            map,
+            held: HashSet::new(),
+        }
+    }
+
+    pub fn handle_event(&mut self, event: &Event) {
+        match *event {
+            Event::ControllerButtonDown { which, button, .. } => {
+                self.held.insert((which, PadInput::Button(button)));
+            }
+            Event::ControllerButtonUp { which, button, .. } => {
+                self.held.remove(&(which, PadInput::Button(button)));
+            }
+            Event::ControllerAxisMotion {
+                which, axis, value, ..
+            } => {
+                let negative = (which, PadInput::AxisNegative(axis));
+                let positive = (which, PadInput::AxisPositive(axis));
+                self.held.remove(&negative);
+                self.held.remove(&positive);
+                if value < -self.map.deadzone {
+                    self.held.insert(negative);
+                } else if value > self.map.deadzone {
+                    self.held.insert(positive);
+                }
+            }
+            Event::ControllerDeviceRemoved { which, .. } => {
+                self.held.retain(|&(id, _)| id != which);
+            }
+            _ => {}
+        }
+    }
+
+    pub fn pressed_keys(&self) -> [bool; 16] {
+        let mut keys = [false; 16];
+        for (_, input) in &self.held {
+            if let Some(&key) = self.map.inputs.get(input) {
+                keys[key] = true;
+            }
+        }
+        keys
+    }
+}
+
+/// Opens game controllers as they are plugged in and maps their inputs to keys.
+pub struct Gamepads {
+    /// None when running headless, where no controller events arrive.
+    subsystem: Option<GameControllerSubsystem>,
+    controllers: HashMap<u32, GameController>,
+    mapper: PadMapper,
+}
+
+impl Gamepads {
+    pub fn new(sdl_context: Option<&sdl2::Sdl>, map: PadMap) -> Self {
+        Gamepads {
+            subsystem: sdl_context.map(|sdl_context| sdl_context.game_controller().unwrap()),
+            controllers: HashMap::new(),
+            mapper: PadMapper::new(map),
+        }
+    }
+
+    /// Handles controller events. SDL also sends an added event for each controller
+    /// connected at startup.
+    pub fn handle_event(&mut self, event: &Event) {
+        match *event {
+            Event::ControllerDeviceAdded { which, .. } => {
+                if let Some(subsystem) = &self.subsystem {
+                    match subsystem.open(which) {
+                        Ok(controller) => {
+                            println!("Controller connected: {}", controller.name());
+                            self.controllers
+                                .insert(controller.instance_id() as u32, controller);
+                        }
+                        Err(err) => println!("Could not open controller {}: {}", which, err),
+                    }
+                }
+            }
+            Event::ControllerDeviceRemoved { which, .. } => {
+                if let Some(controller) = self.controllers.remove(&which) {
+                    println!("Controller disconnected: {}", controller.name());
+                }
+            }
+            _ => {}
+        }
+        self.mapper.handle_event(event);
+    }
+
+    pub fn pressed_keys(&self) -> [bool; 16] {
+        self.mapper.pressed_keys()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn button(down: bool, which: u32, button: Button) -> Event {
+        if down {
+            Event::ControllerButtonDown {
+                timestamp: 0,
+                which,
+                button,
+            }
+        } else {
+            Event::ControllerButtonUp {
+                timestamp: 0,
+                which,
+                button,
+            }
+        }
+    }
+
+    fn axis(axis: Axis, value: i16) -> Event {
+        Event::ControllerAxisMotion {
+            timestamp: 0,
+            which: 0,
+            axis,
+            value,
+        }
+    }
+
+    #[test]
+    fn buttons_press_mapped_keys() {
+        let mut mapper = PadMapper::new(PadMap::default());
+        mapper.handle_event(&button(true, 0, Button::A));
+        assert!(mapper.pressed_keys()[0x5]);
+        mapper.handle_event(&button(false, 0, Button::A));
+        assert!(!mapper.pressed_keys()[0x5]);
+    }
+
+    #[test]
+    fn axis_respects_deadzone() {
+        let mut mapper = PadMapper::new(PadMap::default());
+        mapper.handle_event(&axis(Axis::LeftX, DEFAULT_DEADZONE - 1));
+        assert_eq!(mapper.pressed_keys(), [false; 16]);
+
+        mapper.handle_event(&axis(Axis::LeftX, i16::MIN));
+        assert!(mapper.pressed_keys()[0x4]);
+        assert!(!mapper.pressed_keys()[0x6]);
+
+        mapper.handle_event(&axis(Axis::LeftX, i16::MAX));
+        assert!(!mapper.pressed_keys()[0x4]);
+        assert!(mapper.pressed_keys()[0x6]);
+
+        mapper.handle_event(&axis(Axis::LeftX, 0));
+        assert_eq!(mapper.pressed_keys(), [false; 16]);
+    }
+
+    #[test]
+    fn unplugging_releases_controller_inputs() {
+        let mut mapper = PadMapper::new(PadMap::default());
+        mapper.handle_event(&button(true, 1, Button::DPadUp));
+        mapper.handle_event(&button(true, 2, Button::DPadDown));
+        mapper.handle_event(&Event::ControllerDeviceRemoved {
+            timestamp: 0,
+            which: 1,
+        });
+        let keys = mapper.pressed_keys();
+        assert!(!keys[0x2]);
+        assert!(keys[0x8]);
+    }
+
+    #[test]
+    fn pad_input_names() {
+        assert_eq!(
+            PadInput::from_name("dpleft"),
+            Some(PadInput::Button(Button::DPadLeft))
+        );
+        assert_eq!(
+            PadInput::from_name("righty+"),
+            Some(PadInput::AxisPositive(Axis::RightY))
+        );
+        assert_eq!(PadInput::from_name("leftx"), None);
+        assert_eq!(PadInput::from_name("z"), None);
+    }
+}
@@ -0,0 +1,582 @@
+use crate::crash::Fault;
+use crate::debugger::*;
+use crate::server::*;
+use crate::types::*;
+use std::net::SocketAddr;
+
+/// Something read from the debugger connection.
+#[derive(Debug, PartialEq)]
+enum Incoming {
+    Packet(String),
+    /// A packet whose checksum did not match.
+    Corrupt,
+    /// Ctrl-C in the debugger.
+    Interrupt,
+}
+
+/// Serves the GDB remote serial protocol to one debugger at a time.
+pub struct GdbStub {
+    server: DebugServer,
+    no_ack: bool,
+}
+
+impl GdbStub {
+    pub fn listen(address: SocketAddr) -> Result<GdbStub, ServerError> {
+        Ok(GdbStub {
+            server: DebugServer::listen(address)?,
+            no_ack: false,
+        })
+    }
+
+    /// Handles any packets that have arrived and reports a stop if the target halted.
+    pub fn poll(&mut self, target: &mut dyn Target) {
+        if !self.server.is_connected() {
+            if let Some(address) = self.server.accept() {
+                println!("GDB connected from {}", address);
+                target.debugger().attach();
+                self.no_ack = false;
+            }
+        }
+        if !self.server.is_connected() {
+            return;
+        }
+        if let Err(err) = self.server.receive() {
+            println!("GDB connection closed: {}", err);
+            self.disconnect(target);
+            return;
+        }
+
+        while let Some(incoming) = next_packet(&mut self.server.buffer) {
+            match incoming {
+                Incoming::Interrupt => target.debugger().halt(StopReason::Interrupt),
+                Incoming::Corrupt => self.send_raw(b"-"),
+                Incoming::Packet(packet) => {
+                    if !self.no_ack {
+                        self.send_raw(b"+");
+                    }
+                    self.handle(&packet, target);
+                }
+            }
+            if !self.server.is_connected() {
+                target.debugger().detach();
+                return;
+            }
+        }
+
+        if let Some(reason) = target.debugger().take_stop() {
+            self.send(&stop_reply(reason));
+        }
+    }
+
+    fn disconnect(&mut self, target: &mut dyn Target) {
+        self.server.disconnect();
+        target.debugger().detach();
+    }
+
+    fn handle(&mut self, packet: &str, target: &mut dyn Target) {
+        match packet {
+            "QStartNoAckMode" => {
+                self.send("OK");
+                self.no_ack = true;
+            }
+            "D" => {
+                self.send("OK");
+                println!("GDB detached");
+                self.disconnect(target);
+            }
+            "k" => {
+                println!("GDB detached");
+                self.disconnect(target);
+            }
+            _ => {
+                if let Some(reply) = handle_packet(packet, target) {
+                    self.send(&reply);
+                }
+            }
+        }
+    }
+
+    fn send(&mut self, data: &str) {
+        self.send_raw(encode_packet(data).as_bytes());
+    }
+
+    fn send_raw(&mut self, bytes: &[u8]) {
+        if let Err(err) = self.server.send(bytes) {
+            println!("Could not write to GDB: {}", err);
+        }
+    }
+}
+
+/// Takes the next packet, ack or interrupt off the front of `buffer`, leaving any
+/// incomplete packet in place.
+fn next_packet(buffer: &mut Vec<u8>) -> Option<Incoming> {
+    loop {
+        match buffer.first()? {
+            b'$' => {
+                let end = buffer.iter().position(|&byte| byte == b'#')?;
+                if buffer.len() < end + 3 {
+                    return None;
+                }
+                let data: Vec<u8> = buffer.drain(..end + 3).collect();
+                let body = &data[1..end];
+                let checksum = std::str::from_utf8(&data[end + 1..])
+                    .ok()
+                    .and_then(|text| u8::from_str_radix(text, 16).ok());
+                return Some(if checksum == Some(checksum_of(body)) {
+                    Incoming::Packet(String::from_utf8_lossy(body).to_string())
+                } else {
+                    Incoming::Corrupt
+                });
+            }
+            0x03 => {
+                buffer.remove(0);
+                return Some(Incoming::Interrupt);
+            }
+            // Acks from the debugger; this side never needs to resend.
+            _ => {
+                buffer.remove(0);
+            }
+        }
+    }
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, &byte| sum.wrapping_add(byte))
+}
+
+fn encode_packet(data: &str) -> String {
+    format!("${}#{:02x}", data, checksum_of(data.as_bytes()))
+}
+
+fn stop_reply(reason: StopReason) -> String {
+    match reason {
+        StopReason::Interrupt => "S02".to_string(),
+        StopReason::Breakpoint | StopReason::Step => "S05".to_string(),
+        StopReason::Watchpoint { kind, address, .. } => {
+            let name = match kind {
+                WatchKind::Write => "watch",
+                WatchKind::Read => "rwatch",
+                WatchKind::Access => "awatch",
+            };
+            format!("T05{}:{:x};", name, address)
+        }
+        StopReason::HistoryStart => "T05replaylog:begin;".to_string(),
+        // SIGILL for instructions that cannot be decoded, SIGSEGV for the rest.
+        StopReason::Fault(Fault::InvalidInstruction { .. }) => "S04".to_string(),
+        StopReason::Fault(_) => "S0b".to_string(),
+    }
+}
+
+/// Answers one packet. Returns `None` for packets that resume the target, whose reply
+/// is the stop packet sent when it halts again.
+fn handle_packet(packet: &str, target: &mut dyn Target) -> Option<String> {
+    let mut chars = packet.chars();
+    let (command, arguments) = match chars.next() {
+        Some(command) => (command, chars.as_str()),
+        None => return Some(String::new()),
+    };
+    let reply = match command {
+        '?' => stop_reply(StopReason::Breakpoint),
+        'g' => Register::all()
+            .into_iter()
+            .map(|register| register_hex(target, register))
+            .collect(),
+        'G' => write_registers(target, arguments),
+        'p' => parse_hex(arguments)
+            .and_then(|index| Register::from_index(index as usize))
+            .map(|register| register_hex(target, register))
+            .unwrap_or_else(|| "E01".to_string()),
+        'P' => write_register(target, arguments),
+        'm' => read_memory(target, arguments),
+        'M' => write_memory(target, arguments),
+        'c' | 's' => {
+            if let Some(address) = parse_hex(arguments) {
+                target.write_register(Register::Pc, address as Word);
+            }
+            if command == 'c' {
+                target.debugger().resume();
+            } else {
+                target.debugger().step();
+            }
+            return None;
+        }
+        'b' => {
+            match arguments {
+                "s" => reverse_step(target),
+                "c" => reverse_continue(target),
+                _ => return Some(String::new()),
+            }
+            return None;
+        }
+        'Z' | 'z' => set_breakpoint(target, command == 'Z', arguments),
+        'H' => "OK".to_string(),
+        'q' => match arguments.strip_prefix("Rcmd,") {
+            Some(command) => monitor(target, command),
+            None => query(arguments),
+        },
+        _ => String::new(),
+    };
+    Some(reply)
+}
+
+fn query(query: &str) -> String {
+    if query.starts_with("Supported") {
+        "PacketSize=1000;qXfer:features:read+;QStartNoAckMode+;ReverseStep+;ReverseContinue+"
+            .to_string()
+    } else if query == "Attached" {
+        "1".to_string()
+    } else if let Some(range) = query.strip_prefix("Xfer:features:read:target.xml:") {
+        read_target_xml(range).unwrap_or_else(|| "E01".to_string())
+    } else {
+        String::new()
+    }
+}
+
+/// Runs a hex-encoded `monitor` command. GDB only knows symbols from ELF files, so
+/// `monitor break LABEL` and `monitor delete LABEL` set and clear breakpoints on labels
+/// from the symbol file, and `monitor symbol ADDRESS` names an address.
+fn monitor(target: &mut dyn Target, hex: &str) -> String {
+    let command = match from_hex(hex).and_then(|bytes| String::from_utf8(bytes).ok()) {
+        Some(command) => command,
+        None => return "E01".to_string(),
+    };
+    let words: Vec<&str> = command.split_whitespace().collect();
+    let output = match words[..] {
+        ["break", name] | ["delete", name] => match target.symbols().resolve(name) {
+            Some(address) if words[0] == "break" => {
+                target.debugger().add_breakpoint(address, None);
+                format!("Breakpoint at {:#05x}\n", address)
+            }
+            Some(address) => {
+                target.debugger().remove_breakpoint(address);
+                format!("Deleted breakpoint at {:#05x}\n", address)
+            }
+            None => format!("No label {}\n", name),
+        },
+        ["symbol", address] => match parse_hex(address.trim_start_matches("0x")) {
+            Some(address) => match target.symbols().locate(address as Word) {
+                Some(location) => format!("{}\n", location),
+                None => "No label\n".to_string(),
+            },
+            None => format!("Not an address: {}\n", address),
+        },
+        _ => "Commands: break LABEL, delete LABEL, symbol ADDRESS\n".to_string(),
+    };
+    to_hex(output.as_bytes())
+}
+
+/// A chunk of the target description at `offset,length`, which tells GDB the register
+/// names and sizes.
+fn read_target_xml(range: &str) -> Option<String> {
+    let (offset, length) = parse_pair(range, ',')?;
+    let registers: String = Register::all()
+        .into_iter()
+        .map(|register| {
+            let kind = match register {
+                Register::I => "data_ptr",
+                Register::Pc => "code_ptr",
+                _ => "int",
+            };
+            format!(
+                "<reg name=\"{}\" bitsize=\"{}\" type=\"{}\"/>",
+                register.name(),
+                register.size() * 8,
+                kind
+            )
+        })
+        .collect();
+    let xml = format!(
+        "<?xml version=\"1.0\"?><target version=\"1.0\">\
+         <feature name=\"org.chip8.cpu\">{}</feature></target>",
+        registers
+    );
+    let start = (offset as usize).min(xml.len());
+    let end = (start + length as usize).min(xml.len());
+    let more = if end < xml.len() { "m" } else { "l" };
+    Some(format!("{}{}", more, &xml[start..end]))
+}
+
+fn register_hex(target: &dyn Target, register: Register) -> String {
+    let value = target.read_register(register);
+    to_hex(&value.to_le_bytes()[..register.size()])
+}
+
+fn write_registers(target: &mut dyn Target, hex: &str) -> String {
+    let bytes = match from_hex(hex) {
+        Some(bytes) => bytes,
+        None => return "E01".to_string(),
+    };
+    let mut rest = &bytes[..];
+    let mut written = true;
+    for register in Register::all() {
+        if rest.len() < register.size() {
+            break;
+        }
+        let (value, tail) = rest.split_at(register.size());
+        written &= target.write_register(register, le_value(value));
+        rest = tail;
+    }
+    if written {
+        "OK".to_string()
+    } else {
+        "E01".to_string()
+    }
+}
+
+fn write_register(target: &mut dyn Target, arguments: &str) -> String {
+    let mut parts = arguments.splitn(2, '=');
+    let register = parts
+        .next()
+        .and_then(parse_hex)
+        .and_then(|index| Register::from_index(index as usize));
+    let value = parts.next().and_then(from_hex);
+    match (register, value) {
+        (Some(register), Some(value))
+            if value.len() == register.size()
+                && target.write_register(register, le_value(&value)) =>
+        {
+            "OK".to_string()
+        }
+        _ => "E01".to_string(),
+    }
+}
+
+fn read_memory(target: &dyn Target, arguments: &str) -> String {
+    match parse_pair(arguments, ',') {
+        Some((address, length)) => {
+            let bytes = target.read_memory(address as usize, length as usize);
+            if bytes.is_empty() && length > 0 {
+                "E01".to_string()
+            } else {
+                to_hex(&bytes)
+            }
+        }
+        None => "E01".to_string(),
+    }
+}
+
+fn write_memory(target: &mut dyn Target, arguments: &str) -> String {
+    let mut parts = arguments.splitn(2, ':');
+    let range = parts.next().and_then(|range| parse_pair(range, ','));
+    let data = parts.next().and_then(from_hex);
+    match (range, data) {
+        (Some((address, length)), Some(data))
+            if data.len() == length as usize && target.write_memory(address as usize, &data) =>
+        {
+            "OK".to_string()
+        }
+        _ => "E01".to_string(),
+    }
+}
+
+/// `Z0`/`z0` set and clear software breakpoints; hardware breakpoints (`Z1`) are
+/// treated the same. `Z2` to `Z4` set write, read and access watchpoints on `length`
+/// bytes. GDB checks its own breakpoint conditions, so none are set here.
+fn set_breakpoint(target: &mut dyn Target, insert: bool, arguments: &str) -> String {
+    let mut parts = arguments.split(',');
+    let kind = parts.next();
+    let address = parts.next().and_then(parse_hex);
+    let length = parts.next().and_then(parse_hex);
+    let (address, length) = match (address, length) {
+        (Some(address), Some(length)) => (address as Word, length as Word),
+        _ => return "E01".to_string(),
+    };
+    let watch_kind = match kind {
+        Some("0") | Some("1") => {
+            if insert {
+                target.debugger().add_breakpoint(address, None);
+            } else {
+                target.debugger().remove_breakpoint(address);
+            }
+            return "OK".to_string();
+        }
+        Some("2") => WatchKind::Write,
+        Some("3") => WatchKind::Read,
+        Some("4") => WatchKind::Access,
+        _ => return String::new(),
+    };
+    if insert {
+        target.debugger().add_watchpoint(Watchpoint {
+            start: address,
+            length,
+            kind: watch_kind,
+            condition: None,
+        });
+    } else {
+        target
+            .debugger()
+            .remove_watchpoint(address, length, watch_kind);
+    }
+    "OK".to_string()
+}
+
+fn parse_hex(text: &str) -> Option<u32> {
+    u32::from_str_radix(text, 16).ok()
+}
+
+fn parse_pair(text: &str, separator: char) -> Option<(u32, u32)> {
+    let mut parts = text.splitn(2, separator);
+    Some((parse_hex(parts.next()?)?, parse_hex(parts.next()?)?))
+}
+
+fn le_value(bytes: &[u8]) -> Word {
+    bytes
+        .iter()
+        .rev()
+        .fold(0, |value, &byte| (value << 8) | byte as Word)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    text.as_bytes()
+        .chunks(2)
+        .map(|pair| match pair {
+            [high, low] => Some((hex_digit(*high)? << 4) | hex_digit(*low)?),
+            _ => None,
+        })
+        .collect()
+}
+
+fn hex_digit(digit: u8) -> Option<u8> {
+    (digit as char).to_digit(16).map(|value| value as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::debugger::testing::TestTarget;
+    use crate::symbols::SymbolMap;
+
+    #[test]
+    fn packets_are_framed_and_checked() {
+        let mut buffer = b"+$g#67$m0,1#00\x03$qSup".to_vec();
+        assert_eq!(
+            next_packet(&mut buffer),
+            Some(Incoming::Packet("g".to_string()))
+        );
+        assert_eq!(next_packet(&mut buffer), Some(Incoming::Corrupt));
+        assert_eq!(next_packet(&mut buffer), Some(Incoming::Interrupt));
+        assert_eq!(next_packet(&mut buffer), None);
+        assert_eq!(buffer, b"$qSup");
+        assert_eq!(encode_packet("OK"), "$OK#9a");
+    }
+
+    #[test]
+    fn empty_and_unknown_packets_are_answered_empty() {
+        let mut target = TestTarget::new();
+        // An empty packet, and one whose first byte is not valid UTF-8.
+        let mut buffer = b"$#00$\xff1#30".to_vec();
+        for _ in 0..2 {
+            let packet = match next_packet(&mut buffer) {
+                Some(Incoming::Packet(packet)) => packet,
+                other => panic!("expected a packet, got {:?}", other),
+            };
+            assert_eq!(handle_packet(&packet, &mut target).unwrap(), "");
+        }
+    }
+
+    #[test]
+    fn registers_are_little_endian() {
+        let mut target = TestTarget::new();
+        target.write_register(Register::V(0), 0x12);
+        target.write_register(Register::Pc, 0x0345);
+        let all = handle_packet("g", &mut target).unwrap();
+        assert_eq!(all.len(), 24 * 2);
+        assert!(all.starts_with("12"));
+        assert_eq!(handle_packet("p11", &mut target).unwrap(), "4503");
+
+        assert_eq!(handle_packet("P10=3402", &mut target).unwrap(), "OK");
+        assert_eq!(target.read_register(Register::I), 0x0234);
+        assert_eq!(handle_packet("P10=34", &mut target).unwrap(), "E01");
+        assert_eq!(handle_packet("p15", &mut target).unwrap(), "E01");
+
+        // The stack holds 16 return addresses.
+        assert_eq!(handle_packet("P12=1000", &mut target).unwrap(), "OK");
+        assert_eq!(handle_packet("P12=1100", &mut target).unwrap(), "E01");
+        assert_eq!(target.read_register(Register::Sp), 0x10);
+    }
+
+    #[test]
+    fn memory_reads_and_writes() {
+        let mut target = TestTarget::new();
+        assert_eq!(handle_packet("M200,2:a2f0", &mut target).unwrap(), "OK");
+        assert_eq!(handle_packet("m1ff,4", &mut target).unwrap(), "00a2f000");
+        assert_eq!(handle_packet("mffe,4", &mut target).unwrap(), "0000");
+        assert_eq!(handle_packet("m1000,1", &mut target).unwrap(), "E01");
+        assert_eq!(handle_packet("Mfff,2:0102", &mut target).unwrap(), "E01");
+    }
+
+    #[test]
+    fn breakpoints_and_resuming() {
+        let mut target = TestTarget::new();
+        target.debugger.attach();
+        assert_eq!(handle_packet("Z0,204,2", &mut target).unwrap(), "OK");
+        assert_eq!(handle_packet("Z5,300,1", &mut target).unwrap(), "");
+        assert_eq!(handle_packet("c", &mut target), None);
+        assert!(!target.debugger.is_halted());
+        assert!(target.debugger.breakpoint_hit(0x204, &target));
+        assert!(!target.debugger.breakpoint_hit(0x202, &target));
+
+        assert_eq!(handle_packet("z0,204,2", &mut target).unwrap(), "OK");
+        assert!(!target.debugger.breakpoint_hit(0x204, &target));
+        assert_eq!(handle_packet("s202", &mut target), None);
+        assert_eq!(target.read_register(Register::Pc), 0x202);
+    }
+
+    #[test]
+    fn reverse_execution() {
+        let mut target = TestTarget::new();
+        target.debugger.attach();
+        target.history.push([0; REGISTER_COUNT]);
+        assert_eq!(handle_packet("bs", &mut target), None);
+        assert_eq!(target.debugger.take_stop(), Some(StopReason::Step));
+        assert_eq!(handle_packet("bc", &mut target), None);
+        let reason = target.debugger.take_stop().unwrap();
+        assert_eq!(stop_reply(reason), "T05replaylog:begin;");
+        assert_eq!(handle_packet("bx", &mut target).unwrap(), "");
+    }
+
+    #[test]
+    fn monitor_commands_use_labels() {
+        let mut target = TestTarget::new();
+        target.symbols = SymbolMap::parse("24e draw_player").unwrap();
+        let mut monitor = |command: &str| {
+            let packet = format!("qRcmd,{}", to_hex(command.as_bytes()));
+            let reply = handle_packet(&packet, &mut target).unwrap();
+            String::from_utf8(from_hex(&reply).unwrap()).unwrap()
+        };
+        assert_eq!(monitor("break draw_player"), "Breakpoint at 0x24e\n");
+        assert_eq!(monitor("break nowhere"), "No label nowhere\n");
+        assert_eq!(monitor("symbol 252"), "draw_player+4\n");
+        assert!(target.debugger.breakpoint_hit(0x24e, &target));
+        assert_eq!(handle_packet("qRcmd,zz", &mut target).unwrap(), "E01");
+    }
+
+    #[test]
+    fn watchpoints() {
+        let mut target = TestTarget::new();
+        assert_eq!(handle_packet("Z3,300,2", &mut target).unwrap(), "OK");
+        target.debugger.before_instruction(0x20a, false);
+        target.debugger.memory_access(0x301, 1, false);
+        let hit = target.debugger.watchpoint_hit(&target);
+        target.debugger.after_instruction(hit);
+        let reason = target.debugger.take_stop().unwrap();
+        assert_eq!(stop_reply(reason), "T05rwatch:301;");
+
+        assert_eq!(handle_packet("z3,300,2", &mut target).unwrap(), "OK");
+        target.debugger.memory_access(0x301, 1, false);
+        assert_eq!(target.debugger.watchpoint_hit(&target), None);
+    }
+
+    #[test]
+    fn target_description_is_read_in_chunks() {
+        let first = query("Xfer:features:read:target.xml:0,a");
+        assert_eq!(first, "m<?xml vers");
+        let last = query("Xfer:features:read:target.xml:a,1000");
+        assert!(last.starts_with('l'));
+        assert!(last.contains("<reg name=\"pc\" bitsize=\"16\" type=\"code_ptr\"/>"));
+    }
+}
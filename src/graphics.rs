@@ -1,16 +1,62 @@
+use crate::filter::*;
+use crate::keypad::*;
+use crate::palette::*;
 use bitvec::prelude::*;
-use sdl2::pixels::Color;
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::rect::Rect;
+use sdl2::render::{Canvas, Texture};
+use sdl2::video::Window;
 
-const WIDTH: usize = 64;
-const HEIGHT: usize = 32;
+pub const WIDTH: usize = 64;
+pub const HEIGHT: usize = 32;
+pub const BYTES_PER_PIXEL: usize = 4;
+
+pub type Vram = [[u8; WIDTH]; HEIGHT];
+
+/// Brightness below which a fading pixel is considered dark.
+const FADE_CUTOFF: f32 = 1.0 / 255.0;
+
+/// How VRAM is turned into displayed pixels from one frame to the next.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DisplayMode {
+    /// Shows VRAM exactly as it is at the end of each frame.
+    Raw,
+    /// Lit pixels fade out instead of switching off, keeping `decay` of their
+    /// brightness each frame.
+    Fade { decay: f32 },
+    /// Averages the last two frames, so pixels that flicker show at half brightness.
+    Blend,
+}
+
+/// Per-pixel brightness left on screen by previous frames.
+pub struct Phosphor {
+    intensity: [[f32; WIDTH]; HEIGHT],
+    lit: Vram,
+    previous: Vram,
+}
+
+/// The window the display is shown in, with the textures drawn to it.
+struct Screen {
+    canvas: Canvas<Window>,
+    texture: Texture,
+    keypad_texture: Texture,
+}
 
 pub struct Graphics {
-    vram: [[u8; WIDTH]; HEIGHT],
-    canvas: sdl2::render::Canvas<sdl2::video::Window>,
+    vram: Vram,
+    /// None when running headless, with frames only kept in the framebuffer.
+    screen: Option<Screen>,
+    framebuffer: Vec<u8>,
+    palette: Palette,
+    mode: DisplayMode,
+    phosphor: Phosphor,
+    filters: Filters,
+    show_keypad: bool,
+    keypad_keys: [bool; 16],
 }
 
-impl Graphics {
-    pub fn new(sdl_context: &sdl2::Sdl) -> Self {
+impl Screen {
+    fn new(sdl_context: &sdl2::Sdl, filters: Filters, show_keypad: bool) -> Self {
         let video = sdl_context.video().unwrap();
         let window = video
             .window("rust-chip-8", 800, 600)
@@ -18,13 +64,58 @@ impl Graphics {
             .build()
             .unwrap();
         let mut canvas = window.into_canvas().build().unwrap();
-        canvas
-            .set_logical_size(WIDTH as u32, HEIGHT as u32)
-            .expect("Could not set logical size");
+        set_logical_size(&mut canvas, show_keypad);
+        let scale = filters.scale() as u32;
+        let texture = canvas
+            .create_texture_streaming(
+                PixelFormatEnum::RGBA32,
+                WIDTH as u32 * scale,
+                HEIGHT as u32 * scale,
+            )
+            .expect("Could not create texture");
+        let keypad_texture = canvas
+            .create_texture_streaming(PixelFormatEnum::RGBA32, PANEL_WIDTH as u32, HEIGHT as u32)
+            .expect("Could not create texture");
 
+        Screen {
+            canvas,
+            texture,
+            keypad_texture,
+        }
+    }
+
+    fn draw_keypad(&mut self, keys: &[bool; 16], palette: &Palette) {
+        let mut panel = vec![0; PANEL_WIDTH * HEIGHT * BYTES_PER_PIXEL];
+        draw_panel(&mut panel, keys, palette);
+        self.keypad_texture
+            .update(None, &panel, PANEL_WIDTH * BYTES_PER_PIXEL)
+            .expect("Could not update texture");
+        let area = Rect::new(WIDTH as i32, 0, PANEL_WIDTH as u32, HEIGHT as u32);
+        self.canvas
+            .copy(&self.keypad_texture, None, area)
+            .expect("Could not copy texture");
+    }
+}
+
+impl Graphics {
+    /// Opens a window for the display, or runs without one if there is no SDL context.
+    pub fn new(
+        sdl_context: Option<&sdl2::Sdl>,
+        palette: Palette,
+        mode: DisplayMode,
+        filters: Filters,
+        show_keypad: bool,
+    ) -> Self {
         Graphics {
             vram: [[0; WIDTH]; HEIGHT],
-            canvas,
+            screen: sdl_context.map(|sdl_context| Screen::new(sdl_context, filters, show_keypad)),
+            framebuffer: vec![0; WIDTH * HEIGHT * BYTES_PER_PIXEL],
+            palette,
+            mode,
+            phosphor: Phosphor::new(),
+            filters,
+            show_keypad,
+            keypad_keys: [false; 16],
         }
     }
 
@@ -36,6 +127,24 @@ impl Graphics {
         }
     }
 
+    pub fn vram(&self) -> &Vram {
+        &self.vram
+    }
+
+    pub fn vram_mut(&mut self) -> &mut Vram {
+        &mut self.vram
+    }
+
+    /// VRAM as RGBA pixels in the current palette, without any fading.
+    pub fn vram_image(&self) -> Vec<u8> {
+        let mut image = Vec::with_capacity(WIDTH * HEIGHT * BYTES_PER_PIXEL);
+        for &pixel in self.vram.iter().flatten() {
+            let color = self.palette.colors[pixel as usize & 3];
+            image.extend_from_slice(&[color.r, color.g, color.b, color.a]);
+        }
+        image
+    }
+
     pub fn draw_sprite(&mut self, x: u8, y: u8, sprite_bytes: &[u8]) -> bool {
         let mut collision = false;
         for row in 0..sprite_bytes.len() {
@@ -55,21 +164,265 @@ impl Graphics {
         collision
     }
 
+    /// Advances the display by one frame and presents it. Should be called once per
+    /// 60Hz frame so fading runs at a steady rate.
     pub fn render(&mut self) {
-        for y in 0..HEIGHT {
-            for x in 0..WIDTH {
-                let pixel = self.vram[y][x];
-                let color = if pixel > 0 {
-                    Color::RGB(255, 255, 255)
-                } else {
-                    Color::RGB(0, 0, 0)
-                };
-                self.canvas.set_draw_color(color);
-                self.canvas
-                    .draw_point(sdl2::rect::Point::new(x as i32, y as i32))
-                    .expect("Could not draw point");
+        self.phosphor.update(&self.vram, self.mode);
+        self.phosphor
+            .fill_framebuffer(&mut self.framebuffer, &self.palette);
+        let screen = match &mut self.screen {
+            Some(screen) => screen,
+            None => return,
+        };
+        if self.filters == Filters::default() {
+            screen
+                .texture
+                .update(None, &self.framebuffer, WIDTH * BYTES_PER_PIXEL)
+                .expect("Could not update texture");
+        } else {
+            let filtered = self.filters.apply(&self.framebuffer, WIDTH, HEIGHT);
+            let pitch = WIDTH * self.filters.scale() * BYTES_PER_PIXEL;
+            screen
+                .texture
+                .update(None, &filtered, pitch)
+                .expect("Could not update texture");
+        }
+        if self.show_keypad {
+            screen.canvas.set_draw_color(self.palette.colors[0]);
+            screen.canvas.clear();
+            screen.draw_keypad(&self.keypad_keys, &self.palette);
+            let display = Rect::new(0, 0, WIDTH as u32, HEIGHT as u32);
+            screen
+                .canvas
+                .copy(&screen.texture, None, display)
+                .expect("Could not copy texture");
+        } else {
+            screen
+                .canvas
+                .copy(&screen.texture, None, None)
+                .expect("Could not copy texture");
+        }
+        screen.canvas.present();
+    }
+
+    /// Shows or hides the on-screen keypad, returning whether it is now shown.
+    pub fn toggle_keypad(&mut self) -> bool {
+        self.show_keypad = !self.show_keypad;
+        if let Some(screen) = &mut self.screen {
+            set_logical_size(&mut screen.canvas, self.show_keypad);
+        }
+        self.show_keypad
+    }
+
+    /// Sets the keys to highlight on the keypad. Returns true if the keypad is shown
+    /// and needs drawing again.
+    pub fn set_keypad_keys(&mut self, pressed: [bool; 16]) -> bool {
+        let changed = self.keypad_keys != pressed;
+        self.keypad_keys = pressed;
+        changed && self.show_keypad
+    }
+
+    /// The RGBA pixels of the last rendered frame, one per VRAM cell.
+    pub fn framebuffer(&self) -> &[u8] {
+        &self.framebuffer
+    }
+
+    /// The last rendered frame as shown in the window, with filters applied, or
+    /// scaled up `scale` times with nearest-neighbor sampling if no filter changes the
+    /// size. Returns the RGBA pixels with the width and height.
+    pub fn scaled_frame(&self, scale: usize) -> (Vec<u8>, usize, usize) {
+        if self.filters.scale() > 1 {
+            let scale = self.filters.scale();
+            let frame = self.filters.apply(&self.framebuffer, WIDTH, HEIGHT);
+            (frame, WIDTH * scale, HEIGHT * scale)
+        } else {
+            let frame = nearest_rgba(&self.framebuffer, WIDTH, HEIGHT, scale);
+            (frame, WIDTH * scale, HEIGHT * scale)
+        }
+    }
+
+    /// Whether the display will still change without any new drawing.
+    pub fn is_fading(&self) -> bool {
+        self.phosphor.is_fading(&self.vram, self.mode)
+    }
+
+    pub fn palette(&self) -> &Palette {
+        &self.palette
+    }
+
+    pub fn cycle_palette(&mut self) {
+        self.palette = self.palette.next_theme();
+        println!("Theme: {}", self.palette.name);
+    }
+}
+
+impl Phosphor {
+    pub fn new() -> Self {
+        Phosphor {
+            intensity: [[0.0; WIDTH]; HEIGHT],
+            lit: [[0; WIDTH]; HEIGHT],
+            previous: [[0; WIDTH]; HEIGHT],
+        }
+    }
+
+    pub fn update(&mut self, vram: &Vram, mode: DisplayMode) {
+        for (y, row) in vram.iter().enumerate() {
+            for (x, &pixel) in row.iter().enumerate() {
+                let previous = self.previous[y][x];
+                let intensity = &mut self.intensity[y][x];
+                match mode {
+                    DisplayMode::Raw => *intensity = if pixel > 0 { 1.0 } else { 0.0 },
+                    DisplayMode::Fade { decay } => {
+                        *intensity = if pixel > 0 {
+                            1.0
+                        } else if *intensity * decay < FADE_CUTOFF {
+                            0.0
+                        } else {
+                            *intensity * decay
+                        }
+                    }
+                    DisplayMode::Blend => {
+                        *intensity = ((pixel > 0) as u8 + (previous > 0) as u8) as f32 / 2.0
+                    }
+                }
+                if pixel > 0 {
+                    self.lit[y][x] = pixel;
+                } else if previous > 0 && mode == DisplayMode::Blend {
+                    self.lit[y][x] = previous;
+                }
             }
         }
-        self.canvas.present();
+        self.previous = *vram;
+    }
+
+    pub fn is_fading(&self, vram: &Vram, mode: DisplayMode) -> bool {
+        let part_lit = self
+            .intensity
+            .iter()
+            .flat_map(|row| row.iter())
+            .any(|&intensity| intensity > 0.0 && intensity < 1.0);
+        match mode {
+            DisplayMode::Raw => false,
+            DisplayMode::Fade { .. } => part_lit,
+            // A half-bright pixel only clears once the next frame is blended in.
+            DisplayMode::Blend => part_lit || self.previous != *vram,
+        }
+    }
+
+    /// Converts the pixel brightnesses into RGBA bytes, one pixel per VRAM cell,
+    /// shading from the background to the pixel's palette color.
+    pub fn fill_framebuffer(&self, framebuffer: &mut [u8], palette: &Palette) {
+        let background = palette.colors[0];
+        let pixels = self
+            .intensity
+            .iter()
+            .flatten()
+            .zip(self.lit.iter().flatten());
+        for ((&intensity, &lit), out) in pixels.zip(framebuffer.chunks_exact_mut(BYTES_PER_PIXEL)) {
+            let color = mix(background, palette.colors[lit as usize & 3], intensity);
+            out.copy_from_slice(&[color.r, color.g, color.b, color.a]);
+        }
+    }
+}
+
+/// The window shows the display, and the keypad to its right when enabled, scaled up
+/// to fit.
+fn set_logical_size(canvas: &mut Canvas<Window>, show_keypad: bool) {
+    let width = if show_keypad {
+        WIDTH + PANEL_WIDTH
+    } else {
+        WIDTH
+    };
+    canvas
+        .set_logical_size(width as u32, HEIGHT as u32)
+        .expect("Could not set logical size");
+}
+
+pub fn mix(from: Color, to: Color, amount: f32) -> Color {
+    let channel = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * amount).round() as u8;
+    Color::RGB(
+        channel(from.r, to.r),
+        channel(from.g, to.g),
+        channel(from.b, to.b),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn framebuffer_of(phosphor: &Phosphor) -> Vec<u8> {
+        let mut framebuffer = vec![0; WIDTH * HEIGHT * BYTES_PER_PIXEL];
+        phosphor.fill_framebuffer(&mut framebuffer, &Palette::default());
+        framebuffer
+    }
+
+    #[test]
+    fn raw_mode_maps_pixels_to_rgba() {
+        let mut vram = [[0; WIDTH]; HEIGHT];
+        vram[0][1] = 1;
+        vram[HEIGHT - 1][WIDTH - 1] = 1;
+        let mut phosphor = Phosphor::new();
+        phosphor.update(&vram, DisplayMode::Raw);
+        let framebuffer = framebuffer_of(&phosphor);
+
+        assert_eq!(&framebuffer[0..4], &[0, 0, 0, 255]);
+        assert_eq!(&framebuffer[4..8], &[255, 255, 255, 255]);
+        assert_eq!(&framebuffer[framebuffer.len() - 4..], &[255, 255, 255, 255]);
+    }
+
+    #[test]
+    fn fade_mode_decays_cleared_pixels() {
+        let mode = DisplayMode::Fade { decay: 0.5 };
+        let mut vram = [[0; WIDTH]; HEIGHT];
+        vram[0][0] = 1;
+        let mut phosphor = Phosphor::new();
+        phosphor.update(&vram, mode);
+        vram[0][0] = 0;
+        phosphor.update(&vram, mode);
+
+        assert!(phosphor.is_fading(&vram, mode));
+        assert_eq!(&framebuffer_of(&phosphor)[0..4], &[128, 128, 128, 255]);
+
+        for _ in 0..8 {
+            phosphor.update(&vram, mode);
+        }
+        assert!(!phosphor.is_fading(&vram, mode));
+        assert_eq!(&framebuffer_of(&phosphor)[0..4], &[0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn blend_mode_averages_last_two_frames() {
+        let mut vram = [[0; WIDTH]; HEIGHT];
+        vram[0][0] = 1;
+        let mut phosphor = Phosphor::new();
+        phosphor.update(&vram, DisplayMode::Blend);
+        phosphor.update(&vram, DisplayMode::Blend);
+        vram[0][0] = 0;
+        phosphor.update(&vram, DisplayMode::Blend);
+
+        assert_eq!(&framebuffer_of(&phosphor)[0..4], &[128, 128, 128, 255]);
+        phosphor.update(&vram, DisplayMode::Blend);
+        assert_eq!(&framebuffer_of(&phosphor)[0..4], &[0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn blend_mode_renders_until_ghosts_clear() {
+        let mode = DisplayMode::Blend;
+        let mut vram = [[0; WIDTH]; HEIGHT];
+        vram[0][0] = 1;
+        let mut phosphor = Phosphor::new();
+        phosphor.update(&vram, mode);
+        phosphor.update(&vram, mode);
+        assert!(!phosphor.is_fading(&vram, mode));
+
+        vram[0][0] = 0;
+        assert!(phosphor.is_fading(&vram, mode));
+        phosphor.update(&vram, mode);
+        // The ghost is on screen, so the next frame must still be drawn to clear it.
+        assert!(phosphor.is_fading(&vram, mode));
+        phosphor.update(&vram, mode);
+        assert!(!phosphor.is_fading(&vram, mode));
+        assert_eq!(&framebuffer_of(&phosphor)[0..4], &[0, 0, 0, 255]);
     }
 }
@@ -0,0 +1,150 @@
+use crate::debugger::*;
+use crate::graphics::*;
+use crate::state::*;
+use crate::types::*;
+use std::collections::VecDeque;
+
+/// Instructions remembered for reverse stepping, about a minute and a half at the
+/// normal speed.
+pub const HISTORY_LENGTH: usize = 50_000;
+
+/// What one instruction changed, with the values from before it ran.
+struct Undo {
+    registers: Vec<(usize, Word)>,
+    stack: Vec<(usize, Word)>,
+    memory: Vec<(usize, u8)>,
+    vram: Vec<(usize, [u8; WIDTH])>,
+}
+
+/// A bounded record of recent instructions that can be undone one at a time. The VM
+/// records after each instruction, and the changes are found by comparing with the
+/// state after the one before, so anything changed in between (the timers counting
+/// down, or a debugger edit) is undone along with the next instruction.
+pub struct History {
+    undo: VecDeque<Undo>,
+    capacity: usize,
+    state: MachineState,
+}
+
+impl History {
+    pub fn new(capacity: usize) -> Self {
+        History {
+            undo: VecDeque::new(),
+            capacity,
+            state: MachineState {
+                registers: [0; REGISTER_COUNT],
+                stack: Vec::new(),
+                memory: Vec::new(),
+                vram: [[0; WIDTH]; HEIGHT],
+            },
+        }
+    }
+
+    /// Forgets everything recorded and starts again from `state`.
+    pub fn reset(&mut self, state: MachineState) {
+        self.undo.clear();
+        self.state = state;
+    }
+
+    /// Records the instruction that led to the given state, dropping the oldest one if
+    /// the history is full.
+    pub fn record(
+        &mut self,
+        registers: &[Word; REGISTER_COUNT],
+        stack: &[Word],
+        memory: &[u8],
+        vram: &Vram,
+    ) {
+        let undo = Undo {
+            registers: changes(&mut self.state.registers, registers),
+            stack: changes(&mut self.state.stack, stack),
+            memory: changes(&mut self.state.memory, memory),
+            vram: changes(&mut self.state.vram, vram),
+        };
+        if self.undo.len() == self.capacity {
+            self.undo.pop_front();
+        }
+        self.undo.push_back(undo);
+    }
+
+    /// Undoes the last recorded instruction, returning the state from before it ran,
+    /// or `None` if there is nothing left to undo.
+    pub fn step_back(&mut self) -> Option<&MachineState> {
+        let undo = self.undo.pop_back()?;
+        restore(&mut self.state.registers, undo.registers);
+        restore(&mut self.state.stack, undo.stack);
+        restore(&mut self.state.memory, undo.memory);
+        restore(&mut self.state.vram, undo.vram);
+        Some(&self.state)
+    }
+}
+
+/// Updates `old` to `new`, returning the old values of the entries that changed.
+fn changes<T: Copy + PartialEq>(old: &mut [T], new: &[T]) -> Vec<(usize, T)> {
+    let mut changed = Vec::new();
+    for (index, (old, new)) in old.iter_mut().zip(new).enumerate() {
+        if old != new {
+            changed.push((index, *old));
+            *old = *new;
+        }
+    }
+    changed
+}
+
+fn restore<T>(values: &mut [T], changes: Vec<(usize, T)>) {
+    for (index, value) in changes {
+        values[index] = value;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(pc: Word) -> MachineState {
+        let mut registers = [0; REGISTER_COUNT];
+        registers[Register::Pc.index()] = pc;
+        MachineState {
+            registers,
+            stack: vec![0; 16],
+            memory: vec![0; 4096],
+            vram: [[0; WIDTH]; HEIGHT],
+        }
+    }
+
+    fn record(history: &mut History, state: &MachineState) {
+        history.record(&state.registers, &state.stack, &state.memory, &state.vram);
+    }
+
+    #[test]
+    fn steps_back_through_changes() {
+        let mut history = History::new(10);
+        let start = state(0x200);
+        history.reset(start.clone());
+
+        let mut first = state(0x202);
+        first.memory[0x300] = 7;
+        first.vram[4][10] = 1;
+        record(&mut history, &first);
+        let mut second = first.clone();
+        second.registers[Register::Pc.index()] = 0x206;
+        second.stack[0] = 0x204;
+        record(&mut history, &second);
+
+        assert_eq!(history.step_back(), Some(&first));
+        assert_eq!(history.step_back(), Some(&start));
+        assert_eq!(history.step_back(), None);
+    }
+
+    #[test]
+    fn oldest_instructions_are_dropped() {
+        let mut history = History::new(2);
+        history.reset(state(0x200));
+        for pc in &[0x202, 0x204, 0x206] {
+            record(&mut history, &state(*pc));
+        }
+        assert_eq!(history.step_back(), Some(&state(0x204)));
+        assert_eq!(history.step_back(), Some(&state(0x202)));
+        assert_eq!(history.step_back(), None);
+    }
+}
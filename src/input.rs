@@ -1,82 +1,457 @@
-use sdl2::event::Event;
+use crate::gamepad::*;
+use crate::graphics::WIDTH;
+use crate::keymap::*;
+use crate::keypad::key_at;
+use crate::macros::*;
+use sdl2::event::{Event, WindowEvent};
 use sdl2::keyboard::Keycode;
+use sdl2::mouse::MouseButton;
+use std::collections::{HashSet, VecDeque};
+
+/// Key events kept for the VM; older ones are dropped if it does not read them.
+const MAX_KEY_EVENTS: usize = 32;
+
+/// Emulator commands bound to keys outside the CHIP-8 keypad.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Hotkey {
+    Quit,
+    CycleTheme,
+    Screenshot,
+    ToggleRecording,
+    ToggleMute,
+    ToggleKeypad,
+    RecordMacro,
+    PlayMacro,
+    ToggleDebugWindow,
+}
+
+/// A CHIP-8 key going down or up.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct KeyEvent {
+    pub key: usize,
+    pub pressed: bool,
+}
+
+/// When an Fx0A wait completes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KeyWaitMode {
+    /// On the release of a key pressed during the wait, as on the COSMAC VIP.
+    Release,
+    /// As soon as a key is pressed, as in many later interpreters.
+    Press,
+}
+
+impl KeyWaitMode {
+    pub fn from_name(name: &str) -> Option<KeyWaitMode> {
+        match name {
+            "release" => Some(KeyWaitMode::Release),
+            "press" => Some(KeyWaitMode::Press),
+            _ => None,
+        }
+    }
+}
+
+/// Follows key events during an Fx0A wait to decide which key it gets.
+pub struct KeyWait {
+    mode: KeyWaitMode,
+    pressed: Option<usize>,
+}
+
+impl KeyWait {
+    pub fn new(mode: KeyWaitMode) -> Self {
+        KeyWait {
+            mode,
+            pressed: None,
+        }
+    }
+
+    /// Starts a new wait; keys already held when it starts are ignored.
+    pub fn start(&mut self) {
+        self.pressed = None;
+    }
+
+    /// Returns the key once the wait is over.
+    pub fn handle(&mut self, event: KeyEvent) -> Option<usize> {
+        match (self.mode, event.pressed) {
+            (KeyWaitMode::Press, true) => Some(event.key),
+            (KeyWaitMode::Release, true) => {
+                self.pressed.get_or_insert(event.key);
+                None
+            }
+            (KeyWaitMode::Release, false) if self.pressed == Some(event.key) => {
+                self.pressed = None;
+                Some(event.key)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// CHIP-8 key states built from press and release events. A key pressed at any time
+/// during a frame reads as pressed until the end of that frame, even if it was
+/// released again, so short taps between polls are not lost.
+pub struct KeyState {
+    held: [bool; 16],
+    latched: [bool; 16],
+    events: VecDeque<KeyEvent>,
+}
+
+impl KeyState {
+    pub fn new() -> Self {
+        KeyState {
+            held: [false; 16],
+            latched: [false; 16],
+            events: VecDeque::new(),
+        }
+    }
+
+    /// Sets which keys are held now, recording a press or release event for every
+    /// key that changed.
+    pub fn update(&mut self, held: [bool; 16]) {
+        let changed = held.iter().zip(self.held.iter()).enumerate();
+        for (key, (&now, &before)) in changed {
+            if now == before {
+                continue;
+            }
+            if now {
+                self.latched[key] = true;
+            }
+            if self.events.len() == MAX_KEY_EVENTS {
+                self.events.pop_front();
+            }
+            self.events.push_back(KeyEvent { key, pressed: now });
+        }
+        self.held = held;
+    }
+
+    pub fn is_pressed(&self, key: usize) -> bool {
+        self.held[key] || self.latched[key]
+    }
+
+    pub fn end_frame(&mut self) {
+        self.latched = self.held;
+    }
+
+    pub fn next_event(&mut self) -> Option<KeyEvent> {
+        self.events.pop_front()
+    }
+
+    pub fn clear_events(&mut self) {
+        self.events.clear();
+    }
+}
 
 pub struct Input {
-    event_pump: sdl2::EventPump,
-    keys: [bool; 16],
+    /// None when running headless, with no keys ever pressed.
+    event_pump: Option<sdl2::EventPump>,
+    host_keys: HashSet<Keycode>,
+    clicked_key: Option<usize>,
+    keypad_enabled: bool,
+    /// The debug window, whose close button hides it rather than quitting.
+    debug_window_id: Option<u32>,
+    /// Key presses and clicks in the debug window, which do not press CHIP-8 keys.
+    debug_window_events: Vec<Event>,
+    keys: KeyState,
+    keymap: KeyMap,
+    gamepads: Gamepads,
+    turbo: Turbo,
+    recorded_macro: Macro,
 }
 
 impl Input {
-    pub fn new(sdl_context: &sdl2::Sdl) -> Self {
-        let event_pump = sdl_context.event_pump().unwrap();
+    pub fn new(
+        sdl_context: Option<&sdl2::Sdl>,
+        keymap: KeyMap,
+        pad_map: PadMap,
+        turbo: Turbo,
+    ) -> Self {
         Input {
-            event_pump,
-            keys: [false; 16],
+            event_pump: sdl_context.map(|sdl_context| sdl_context.event_pump().unwrap()),
+            host_keys: HashSet::new(),
+            clicked_key: None,
+            keypad_enabled: false,
+            debug_window_id: None,
+            debug_window_events: Vec::new(),
+            keys: KeyState::new(),
+            keymap,
+            gamepads: Gamepads::new(sdl_context, pad_map),
+            turbo,
+            recorded_macro: Macro::new(),
         }
     }
 
-    pub fn process_input(&mut self) -> bool {
-        let mut quit = false;
-        for event in self.event_pump.poll_iter() {
+    pub fn process_input(&mut self) -> Vec<Hotkey> {
+        let mut hotkeys = Vec::new();
+        let events: Vec<Event> = match &mut self.event_pump {
+            Some(event_pump) => event_pump.poll_iter().collect(),
+            None => Vec::new(),
+        };
+        for event in events {
+            self.gamepads.handle_event(&event);
+            if self.is_debug_window_input(&event) {
+                self.debug_window_events.push(event);
+                continue;
+            }
             match event {
-                Event::Quit { .. }
-                | Event::KeyDown {
-                    keycode: Some(Keycode::Escape),
+                Event::Quit { .. } => hotkeys.push(Hotkey::Quit),
+                Event::KeyDown {
+                    keycode: Some(keycode),
+                    repeat: false,
+                    ..
+                } => {
+                    match self.keymap.hotkey(keycode) {
+                        Some(Hotkey::RecordMacro) => self.toggle_macro_recording(),
+                        Some(Hotkey::PlayMacro) => self.recorded_macro.play(),
+                        Some(hotkey) => hotkeys.push(hotkey),
+                        None => {}
+                    }
+                    self.host_keys.insert(keycode);
+                }
+                Event::KeyUp {
+                    keycode: Some(keycode),
+                    ..
+                } => {
+                    self.host_keys.remove(&keycode);
+                }
+                // Touches arrive as mouse events too, so they press keys the same way.
+                Event::MouseButtonDown {
+                    mouse_btn: MouseButton::Left,
+                    x,
+                    y,
+                    ..
+                } if self.keypad_enabled => self.clicked_key = key_at(x - WIDTH as i32, y),
+                Event::MouseButtonUp {
+                    mouse_btn: MouseButton::Left,
+                    ..
+                } => self.clicked_key = None,
+                // Key ups are not seen while another window has focus.
+                Event::Window {
+                    win_event: WindowEvent::FocusLost,
                     ..
-                } => quit = true,
+                } => {
+                    self.host_keys.clear();
+                    self.clicked_key = None;
+                }
+                // With a second window open, SDL only sends Quit once both are closed.
+                Event::Window {
+                    window_id,
+                    win_event: WindowEvent::Close,
+                    ..
+                } => hotkeys.push(if Some(window_id) == self.debug_window_id {
+                    Hotkey::ToggleDebugWindow
+                } else {
+                    Hotkey::Quit
+                }),
                 _ => {}
             }
+            self.update_keys();
         }
 
-        self.keys = [false; 16];
-
-        let sdl_keys: Vec<Keycode> = self
-            .event_pump
-            .keyboard_state()
-            .pressed_scancodes()
-            .filter_map(Keycode::from_scancode)
-            .collect();
-
-        for key in sdl_keys {
-            let index = match key {
-                Keycode::Num1 => Some(0x1),
-                Keycode::Num2 => Some(0x2),
-                Keycode::Num3 => Some(0x3),
-                Keycode::Num4 => Some(0xc),
-                Keycode::Q => Some(0x4),
-                Keycode::W => Some(0x5),
-                Keycode::E => Some(0x6),
-                Keycode::R => Some(0xd),
-                Keycode::A => Some(0x7),
-                Keycode::S => Some(0x8),
-                Keycode::D => Some(0x9),
-                Keycode::F => Some(0xe),
-                Keycode::Z => Some(0xa),
-                Keycode::X => Some(0x0),
-                Keycode::C => Some(0xb),
-                Keycode::V => Some(0xf),
-                _ => None,
-            };
-
-            if let Some(i) = index {
-                self.keys[i] = true;
+        hotkeys
+    }
+
+    fn update_keys(&mut self) {
+        let mut held = self.gamepads.pressed_keys();
+        for (held, &played) in held.iter_mut().zip(self.recorded_macro.keys().iter()) {
+            *held |= played;
+        }
+        if let Some(key) = self.clicked_key {
+            held[key] = true;
+        }
+        for keycode in &self.host_keys {
+            if let Some(key) = self.keymap.chip8_key(*keycode) {
+                held[key] = true;
+            }
+            if let Some(key) = self.keymap.turbo_key(*keycode) {
+                held[key] |= self.turbo.is_on();
             }
         }
+        self.keys.update(held);
+    }
 
-        quit
+    fn toggle_macro_recording(&mut self) {
+        if self.recorded_macro.toggle_recording() {
+            println!("Recording macro");
+        } else {
+            println!("Recorded {} frame macro", self.recorded_macro.len());
+        }
     }
 
     pub fn is_pressed(&self, key: usize) -> bool {
-        self.keys[key]
+        self.keys.is_pressed(key)
+    }
+
+    pub fn pressed_keys(&self) -> [bool; 16] {
+        let mut pressed = [false; 16];
+        for (key, state) in pressed.iter_mut().enumerate() {
+            *state = self.is_pressed(key);
+        }
+        pressed
     }
 
-    pub fn get_first_pressed_key(&self) -> Option<usize> {
-        for i in 0..self.keys.len() {
-            if self.keys[i] {
-                return Some(i);
+    /// Lets mouse clicks and touches on the on-screen keypad press keys.
+    pub fn set_keypad_enabled(&mut self, enabled: bool) {
+        self.keypad_enabled = enabled;
+        if !enabled {
+            self.clicked_key = None;
+        }
+    }
+
+    /// Tells apart events from the debug window, which has the ID `window_id`.
+    pub fn set_debug_window(&mut self, window_id: u32) {
+        self.debug_window_id = Some(window_id);
+    }
+
+    /// Key presses other than hotkeys, clicks and scrolling in the debug window.
+    fn is_debug_window_input(&self, event: &Event) -> bool {
+        let window = match *event {
+            Event::KeyDown {
+                keycode: Some(keycode),
+                window_id,
+                ..
+            } if self.keymap.hotkey(keycode).is_none() => window_id,
+            Event::MouseButtonDown { window_id, .. } | Event::MouseWheel { window_id, .. } => {
+                window_id
             }
+            _ => return false,
+        };
+        Some(window) == self.debug_window_id
+    }
+
+    pub fn take_debug_window_events(&mut self) -> Vec<Event> {
+        std::mem::take(&mut self.debug_window_events)
+    }
+
+    /// Starts a new frame of latched key states.
+    pub fn end_frame(&mut self) {
+        if self.recorded_macro.end_frame(self.pressed_keys()) {
+            println!(
+                "Recorded {} frame macro (limit reached)",
+                self.recorded_macro.len()
+            );
+        }
+        let turbo_held = self
+            .host_keys
+            .iter()
+            .any(|keycode| self.keymap.turbo_key(*keycode).is_some());
+        if turbo_held {
+            self.turbo.next_frame();
+        } else {
+            self.turbo.reset();
+        }
+        self.update_keys();
+        self.keys.end_frame();
+    }
+
+    /// The oldest key press or release not yet read.
+    pub fn next_key_event(&mut self) -> Option<KeyEvent> {
+        self.keys.next_event()
+    }
+
+    pub fn clear_key_events(&mut self) {
+        self.keys.clear_events();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn held(keys: &[usize]) -> [bool; 16] {
+        let mut held = [false; 16];
+        for &key in keys {
+            held[key] = true;
+        }
+        held
+    }
+
+    #[test]
+    fn tap_within_frame_is_latched() {
+        let mut state = KeyState::new();
+        state.update(held(&[0x5]));
+        state.update(held(&[]));
+        assert!(state.is_pressed(0x5));
+
+        state.end_frame();
+        assert!(!state.is_pressed(0x5));
+    }
+
+    #[test]
+    fn held_key_stays_pressed_across_frames() {
+        let mut state = KeyState::new();
+        state.update(held(&[0xa]));
+        state.end_frame();
+        assert!(state.is_pressed(0xa));
+    }
+
+    #[test]
+    fn changes_are_queued_as_events() {
+        let mut state = KeyState::new();
+        state.update(held(&[0x1]));
+        state.update(held(&[0x1, 0x2]));
+        state.update(held(&[0x2]));
+
+        let events: Vec<KeyEvent> = std::iter::from_fn(|| state.next_event()).collect();
+        assert_eq!(
+            events,
+            vec![
+                KeyEvent {
+                    key: 0x1,
+                    pressed: true
+                },
+                KeyEvent {
+                    key: 0x2,
+                    pressed: true
+                },
+                KeyEvent {
+                    key: 0x1,
+                    pressed: false
+                },
+            ]
+        );
+    }
+
+    fn press(key: usize) -> KeyEvent {
+        KeyEvent { key, pressed: true }
+    }
+
+    fn release(key: usize) -> KeyEvent {
+        KeyEvent {
+            key,
+            pressed: false,
+        }
+    }
+
+    #[test]
+    fn release_wait_records_key_on_release() {
+        let mut wait = KeyWait::new(KeyWaitMode::Release);
+        wait.start();
+        assert_eq!(wait.handle(release(0x7)), None);
+        assert_eq!(wait.handle(press(0x2)), None);
+        assert_eq!(wait.handle(press(0x3)), None);
+        assert_eq!(wait.handle(release(0x3)), None);
+        assert_eq!(wait.handle(release(0x2)), Some(0x2));
+    }
+
+    #[test]
+    fn press_wait_records_key_on_press() {
+        let mut wait = KeyWait::new(KeyWaitMode::Press);
+        wait.start();
+        assert_eq!(wait.handle(release(0x7)), None);
+        assert_eq!(wait.handle(press(0x2)), Some(0x2));
+    }
+
+    #[test]
+    fn event_queue_keeps_newest() {
+        let mut state = KeyState::new();
+        for _ in 0..MAX_KEY_EVENTS {
+            state.update(held(&[0x3]));
+            state.update(held(&[]));
         }
+        state.update(held(&[0x4]));
 
-        None
+        let events: Vec<KeyEvent> = std::iter::from_fn(|| state.next_event()).collect();
+        assert_eq!(events.len(), MAX_KEY_EVENTS);
+        assert_eq!(events.last().unwrap().key, 0x4);
     }
 }
@@ -1,8 +1,9 @@
 use crate::types::*;
 use crate::util::*;
 use snafu::Snafu;
+use std::fmt;
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Instruction {
     Sys,
     Cls,
@@ -39,6 +40,94 @@ pub enum Instruction {
     LoadBVx { vx: Nibble },
     StoreVxArray { vx: Nibble },
     ReadVxArray { vx: Nibble },
+    LoadAudioPattern,
+    SetPitchVx { vx: Nibble },
+}
+
+/// Mnemonics used when disassembling, in the style of Cowgod's reference, with `AUDIO`
+/// and `PITCH` for the XO-CHIP sound instructions.
+pub const MNEMONICS: [&str; 22] = [
+    "SYS", "CLS", "RET", "JP", "CALL", "SE", "SNE", "LD", "ADD", "OR", "AND", "XOR", "SUB", "SHR",
+    "SUBN", "SHL", "RND", "DRW", "SKP", "SKNP", "AUDIO", "PITCH",
+];
+
+impl Instruction {
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Instruction::Sys => "SYS",
+            Instruction::Cls => "CLS",
+            Instruction::Ret => "RET",
+            Instruction::Jp { .. } | Instruction::JpV0PlusAddr { .. } => "JP",
+            Instruction::Call { .. } => "CALL",
+            Instruction::SeVxByte { .. } | Instruction::SeVxVy { .. } => "SE",
+            Instruction::SnVxByte { .. } | Instruction::SneVxVy { .. } => "SNE",
+            Instruction::AddVxByte { .. }
+            | Instruction::AddVxVy { .. }
+            | Instruction::AddIVx { .. } => "ADD",
+            Instruction::OrVxVy { .. } => "OR",
+            Instruction::AndVxVy { .. } => "AND",
+            Instruction::XorVxVy { .. } => "XOR",
+            Instruction::SubVxVy { .. } => "SUB",
+            Instruction::ShrVxVy { .. } => "SHR",
+            Instruction::SubnVxVy { .. } => "SUBN",
+            Instruction::ShlVxVy { .. } => "SHL",
+            Instruction::RndVxByte { .. } => "RND",
+            Instruction::DrwVxVy { .. } => "DRW",
+            Instruction::SkipPressedVx { .. } => "SKP",
+            Instruction::SkipNotPressedVx { .. } => "SKNP",
+            Instruction::LoadAudioPattern => "AUDIO",
+            Instruction::SetPitchVx { .. } => "PITCH",
+            _ => "LD",
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = self.mnemonic();
+        match *self {
+            Instruction::Sys
+            | Instruction::Cls
+            | Instruction::Ret
+            | Instruction::LoadAudioPattern => write!(f, "{}", name),
+            Instruction::Jp { address } | Instruction::Call { address } => {
+                write!(f, "{} {:#05x}", name, address)
+            }
+            Instruction::LdIFromAddr { address } => write!(f, "LD I, {:#05x}", address),
+            Instruction::JpV0PlusAddr { address } => write!(f, "JP V0, {:#05x}", address),
+            Instruction::SeVxByte { vx, byte }
+            | Instruction::SnVxByte { vx, byte }
+            | Instruction::LdVxByte { vx, byte }
+            | Instruction::AddVxByte { vx, byte }
+            | Instruction::RndVxByte { vx, byte } => {
+                write!(f, "{} V{:X}, {:#04x}", name, vx, byte)
+            }
+            Instruction::SeVxVy { vx, vy }
+            | Instruction::LdVxVy { vx, vy }
+            | Instruction::OrVxVy { vx, vy }
+            | Instruction::AndVxVy { vx, vy }
+            | Instruction::XorVxVy { vx, vy }
+            | Instruction::AddVxVy { vx, vy }
+            | Instruction::SubVxVy { vx, vy }
+            | Instruction::ShrVxVy { vx, vy }
+            | Instruction::SubnVxVy { vx, vy }
+            | Instruction::ShlVxVy { vx, vy }
+            | Instruction::SneVxVy { vx, vy } => write!(f, "{} V{:X}, V{:X}", name, vx, vy),
+            Instruction::DrwVxVy { vx, vy, n } => write!(f, "DRW V{:X}, V{:X}, {}", vx, vy, n),
+            Instruction::SkipPressedVx { vx }
+            | Instruction::SkipNotPressedVx { vx }
+            | Instruction::SetPitchVx { vx } => write!(f, "{} V{:X}", name, vx),
+            Instruction::LoadDelayTimerVx { vx } => write!(f, "LD V{:X}, DT", vx),
+            Instruction::LoadKeyVx { vx } => write!(f, "LD V{:X}, K", vx),
+            Instruction::SetDelayTimerVx { vx } => write!(f, "LD DT, V{:X}", vx),
+            Instruction::SetSoundTimerVx { vx } => write!(f, "LD ST, V{:X}", vx),
+            Instruction::AddIVx { vx } => write!(f, "ADD I, V{:X}", vx),
+            Instruction::LoadFVx { vx } => write!(f, "LD F, V{:X}", vx),
+            Instruction::LoadBVx { vx } => write!(f, "LD B, V{:X}", vx),
+            Instruction::StoreVxArray { vx } => write!(f, "LD [I], V{:X}", vx),
+            Instruction::ReadVxArray { vx } => write!(f, "LD V{:X}, [I]", vx),
+        }
+    }
 }
 
 #[derive(Debug, Snafu)]
@@ -152,6 +241,7 @@ pub fn decode(encoded_instr: Word) -> Result<Instruction, InstructionError> {
             _ => Err(InstructionError::BadInstruction),
         },
         0xF000 => match encoded_instr & 0x00ff {
+            0x02 if register_x(encoded_instr) == 0 => Ok(Instruction::LoadAudioPattern),
             0x07 => Ok(Instruction::LoadDelayTimerVx {
                 vx: register_x(encoded_instr),
             }),
@@ -179,6 +269,9 @@ pub fn decode(encoded_instr: Word) -> Result<Instruction, InstructionError> {
             0x65 => Ok(Instruction::ReadVxArray {
                 vx: register_x(encoded_instr),
             }),
+            0x3a => Ok(Instruction::SetPitchVx {
+                vx: register_x(encoded_instr),
+            }),
             _ => Err(InstructionError::BadInstruction),
         },
         _ => Err(InstructionError::BadInstruction),
@@ -445,6 +538,18 @@ mod tests {
         assert_eq!(decoded.unwrap(), Instruction::ReadVxArray { vx: 6 });
     }
 
+    #[test]
+    fn decode_load_audio_pattern() {
+        let decoded = decode(0xf002);
+        assert_eq!(decoded.unwrap(), Instruction::LoadAudioPattern);
+    }
+
+    #[test]
+    fn decode_set_pitch_vx() {
+        let decoded = decode(0xf53a);
+        assert_eq!(decoded.unwrap(), Instruction::SetPitchVx { vx: 5 });
+    }
+
     #[test]
     fn decode_bad_f_prefix_instruction() {
         let decoded = decode(0xF288);
@@ -453,4 +558,28 @@ mod tests {
             _ => assert!(false, "Expected BadInstruction error"),
         };
     }
+
+    #[test]
+    fn disassemble() {
+        let text = |word| decode(word).unwrap().to_string();
+        assert_eq!(text(0x00e0), "CLS");
+        assert_eq!(text(0x2765), "CALL 0x765");
+        assert_eq!(text(0x3a05), "SE VA, 0x05");
+        assert_eq!(text(0x8ab4), "ADD VA, VB");
+        assert_eq!(text(0xa024), "LD I, 0x024");
+        assert_eq!(text(0xd12f), "DRW V1, V2, 15");
+        assert_eq!(text(0xf30a), "LD V3, K");
+        assert_eq!(text(0xf355), "LD [I], V3");
+        assert_eq!(text(0xf002), "AUDIO");
+    }
+
+    #[test]
+    fn mnemonics_are_listed() {
+        for word in &[
+            0x00e0, 0x1200, 0x4a00, 0x8126, 0xc0ff, 0xe1a1, 0xf03a, 0xf01e,
+        ] {
+            let instruction = decode(*word).unwrap();
+            assert!(MNEMONICS.contains(&instruction.mnemonic()));
+        }
+    }
 }
@@ -0,0 +1,251 @@
+use crate::input::Hotkey;
+use sdl2::keyboard::Keycode;
+use std::collections::HashMap;
+
+/// Host keys in the positions of the CHIP-8 keypad, row by row:
+///
+/// ```text
+/// 1 2 3 C
+/// 4 5 6 D
+/// 7 8 9 E
+/// A 0 B F
+/// ```
+pub const KEYPAD_ORDER: [usize; 16] = [
+    0x1, 0x2, 0x3, 0xc, 0x4, 0x5, 0x6, 0xd, 0x7, 0x8, 0x9, 0xe, 0xa, 0x0, 0xb, 0xf,
+];
+
+const QWERTY: [Keycode; 16] = [
+    Keycode::Num1,
+    Keycode::Num2,
+    Keycode::Num3,
+    Keycode::Num4,
+    Keycode::Q,
+    Keycode::W,
+    Keycode::E,
+    Keycode::R,
+    Keycode::A,
+    Keycode::S,
+    Keycode::D,
+    Keycode::F,
+    Keycode::Z,
+    Keycode::X,
+    Keycode::C,
+    Keycode::V,
+];
+
+const AZERTY: [Keycode; 16] = [
+    Keycode::Num1,
+    Keycode::Num2,
+    Keycode::Num3,
+    Keycode::Num4,
+    Keycode::A,
+    Keycode::Z,
+    Keycode::E,
+    Keycode::R,
+    Keycode::Q,
+    Keycode::S,
+    Keycode::D,
+    Keycode::F,
+    Keycode::W,
+    Keycode::X,
+    Keycode::C,
+    Keycode::V,
+];
+
+const DVORAK: [Keycode; 16] = [
+    Keycode::Num1,
+    Keycode::Num2,
+    Keycode::Num3,
+    Keycode::Num4,
+    Keycode::Quote,
+    Keycode::Comma,
+    Keycode::Period,
+    Keycode::P,
+    Keycode::A,
+    Keycode::O,
+    Keycode::E,
+    Keycode::U,
+    Keycode::Semicolon,
+    Keycode::Q,
+    Keycode::J,
+    Keycode::K,
+];
+
+const NUMPAD: [Keycode; 16] = [
+    Keycode::Kp7,
+    Keycode::Kp8,
+    Keycode::Kp9,
+    Keycode::KpDivide,
+    Keycode::Kp4,
+    Keycode::Kp5,
+    Keycode::Kp6,
+    Keycode::KpMultiply,
+    Keycode::Kp1,
+    Keycode::Kp2,
+    Keycode::Kp3,
+    Keycode::KpMinus,
+    Keycode::Kp0,
+    Keycode::KpPeriod,
+    Keycode::KpEnter,
+    Keycode::KpPlus,
+];
+
+const HOTKEYS: [(&str, Hotkey, Keycode); 9] = [
+    ("quit", Hotkey::Quit, Keycode::Escape),
+    ("keypad", Hotkey::ToggleKeypad, Keycode::F1),
+    ("theme", Hotkey::CycleTheme, Keycode::F2),
+    ("debug-window", Hotkey::ToggleDebugWindow, Keycode::F3),
+    ("mute", Hotkey::ToggleMute, Keycode::F5),
+    ("macro-record", Hotkey::RecordMacro, Keycode::F6),
+    ("macro-play", Hotkey::PlayMacro, Keycode::F7),
+    ("record", Hotkey::ToggleRecording, Keycode::F9),
+    ("screenshot", Hotkey::Screenshot, Keycode::F12),
+];
+
+/// Which host keys press which CHIP-8 keys and trigger which hotkeys.
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeyMap {
+    keys: HashMap<Keycode, usize>,
+    hotkeys: HashMap<Keycode, Hotkey>,
+    turbo: HashMap<Keycode, usize>,
+}
+
+impl KeyMap {
+    /// The keypad laid out on the given keyboard layout (`qwerty`, `azerty`, `dvorak`
+    /// or `numpad`), with the default hotkeys.
+    pub fn preset(layout: &str) -> Option<KeyMap> {
+        let keycodes = match layout {
+            "qwerty" => QWERTY,
+            "azerty" => AZERTY,
+            "dvorak" => DVORAK,
+            "numpad" => NUMPAD,
+            _ => return None,
+        };
+
+        Some(KeyMap {
+            keys: keycodes
+                .iter()
+                .cloned()
+                .zip(KEYPAD_ORDER.iter().cloned())
+                .collect(),
+            hotkeys: HOTKEYS
+                .iter()
+                .map(|&(_, hotkey, keycode)| (keycode, hotkey))
+                .collect(),
+            turbo: HashMap::new(),
+        })
+    }
+
+    pub fn hotkey_by_name(name: &str) -> Option<Hotkey> {
+        HOTKEYS
+            .iter()
+            .find(|(hotkey_name, ..)| *hotkey_name == name)
+            .map(|&(_, hotkey, _)| hotkey)
+    }
+
+    pub fn hotkey_names() -> Vec<&'static str> {
+        HOTKEYS.iter().map(|(name, ..)| *name).collect()
+    }
+
+    pub fn chip8_key(&self, keycode: Keycode) -> Option<usize> {
+        self.keys.get(&keycode).cloned()
+    }
+
+    pub fn hotkey(&self, keycode: Keycode) -> Option<Hotkey> {
+        self.hotkeys.get(&keycode).cloned()
+    }
+
+    /// The CHIP-8 key autofired while `keycode` is held.
+    pub fn turbo_key(&self, keycode: Keycode) -> Option<usize> {
+        self.turbo.get(&keycode).cloned()
+    }
+
+    /// Binds `keycode` to CHIP-8 key `key`, replacing the key's previous binding and
+    /// any hotkey on `keycode`.
+    pub fn bind_key(&mut self, key: usize, keycode: Keycode) {
+        self.keys.retain(|_, bound| *bound != key);
+        self.hotkeys.remove(&keycode);
+        self.turbo.remove(&keycode);
+        self.keys.insert(keycode, key);
+    }
+
+    /// Binds `keycode` to `hotkey`, replacing the hotkey's previous binding and any
+    /// CHIP-8 key on `keycode`.
+    pub fn bind_hotkey(&mut self, hotkey: Hotkey, keycode: Keycode) {
+        self.hotkeys.retain(|_, bound| *bound != hotkey);
+        self.keys.remove(&keycode);
+        self.turbo.remove(&keycode);
+        self.hotkeys.insert(keycode, hotkey);
+    }
+
+    /// Makes `keycode` autofire CHIP-8 key `key`, in addition to the key's normal
+    /// binding. Replaces any other binding on `keycode`.
+    pub fn bind_turbo(&mut self, key: usize, keycode: Keycode) {
+        self.keys.remove(&keycode);
+        self.hotkeys.remove(&keycode);
+        self.turbo.insert(keycode, key);
+    }
+}
+
+impl Default for KeyMap {
+    fn default() -> Self {
+        KeyMap::preset("qwerty").unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_layout_matches_keypad() {
+        let keymap = KeyMap::default();
+        assert_eq!(keymap.chip8_key(Keycode::Num4), Some(0xc));
+        assert_eq!(keymap.chip8_key(Keycode::X), Some(0x0));
+        assert_eq!(keymap.chip8_key(Keycode::V), Some(0xf));
+        assert_eq!(keymap.hotkey(Keycode::F12), Some(Hotkey::Screenshot));
+    }
+
+    #[test]
+    fn presets_cover_all_keys() {
+        for layout in &["qwerty", "azerty", "dvorak", "numpad"] {
+            let keymap = KeyMap::preset(layout).unwrap();
+            let mut keys: Vec<usize> = keymap.keys.values().cloned().collect();
+            keys.sort();
+            assert_eq!(keys, (0..16).collect::<Vec<usize>>(), "{}", layout);
+        }
+        assert_eq!(KeyMap::preset("colemak"), None);
+    }
+
+    #[test]
+    fn bind_key_replaces_previous_binding() {
+        let mut keymap = KeyMap::default();
+        keymap.bind_key(0x5, Keycode::Up);
+        assert_eq!(keymap.chip8_key(Keycode::Up), Some(0x5));
+        assert_eq!(keymap.chip8_key(Keycode::W), None);
+    }
+
+    #[test]
+    fn bind_key_takes_over_hotkey() {
+        let mut keymap = KeyMap::default();
+        keymap.bind_key(0x0, Keycode::F2);
+        assert_eq!(keymap.hotkey(Keycode::F2), None);
+        assert_eq!(keymap.chip8_key(Keycode::F2), Some(0x0));
+
+        keymap.bind_hotkey(Hotkey::CycleTheme, Keycode::T);
+        assert_eq!(keymap.hotkey(Keycode::T), Some(Hotkey::CycleTheme));
+    }
+
+    #[test]
+    fn turbo_keys_are_separate_from_normal_keys() {
+        let mut keymap = KeyMap::default();
+        keymap.bind_turbo(0x5, Keycode::Space);
+        keymap.bind_turbo(0x6, Keycode::E);
+        assert_eq!(keymap.turbo_key(Keycode::Space), Some(0x5));
+        assert_eq!(keymap.chip8_key(Keycode::W), Some(0x5));
+        assert_eq!(keymap.chip8_key(Keycode::E), None);
+
+        keymap.bind_key(0x6, Keycode::E);
+        assert_eq!(keymap.turbo_key(Keycode::E), None);
+    }
+}
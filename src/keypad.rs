@@ -0,0 +1,92 @@
+use crate::graphics::{mix, BYTES_PER_PIXEL, HEIGHT};
+use crate::keymap::KEYPAD_ORDER;
+use crate::palette::*;
+use crate::vm::FONT_SET;
+
+/// Size of one key on the panel, in CHIP-8 pixels.
+const CELL: usize = 8;
+
+/// Width of the on-screen keypad shown to the right of the display. It is as tall as
+/// the display, with the keys in the same 4x4 layout as the original keypad.
+pub const PANEL_WIDTH: usize = CELL * 4;
+
+/// The CHIP-8 key at panel coordinates `x`, `y`, if any.
+pub fn key_at(x: i32, y: i32) -> Option<usize> {
+    if x < 0 || y < 0 || x >= PANEL_WIDTH as i32 || y >= HEIGHT as i32 {
+        return None;
+    }
+    let column = x as usize / CELL;
+    let row = y as usize / CELL;
+    Some(KEYPAD_ORDER[row * 4 + column])
+}
+
+/// Draws the keypad into a `PANEL_WIDTH` by `HEIGHT` RGBA buffer. Each key shows its
+/// digit from the built-in font, and pressed keys are drawn inverted.
+pub fn draw_panel(rgba: &mut [u8], pressed: &[bool; 16], palette: &Palette) {
+    let background = palette.colors[0];
+    let foreground = palette.colors[1];
+    let face = mix(background, foreground, 0.25);
+    for (i, out) in rgba.chunks_exact_mut(BYTES_PER_PIXEL).enumerate() {
+        let (x, y) = (i % PANEL_WIDTH, i / PANEL_WIDTH);
+        let key = KEYPAD_ORDER[(y / CELL) * 4 + x / CELL];
+        let (cell_x, cell_y) = (x % CELL, y % CELL);
+        // A one pixel border around each key face, leaving a gap between keys.
+        let on_face = (1..CELL - 1).contains(&cell_x) && (1..CELL - 1).contains(&cell_y);
+        // The 4x5 glyph sits one pixel in from the sides and top of the face, and reaches
+        // its bottom row, as the face is only six pixels tall.
+        let on_glyph = (2..6).contains(&cell_x)
+            && (2..7).contains(&cell_y)
+            && FONT_SET[key * 5 + cell_y - 2] & (0x80 >> (cell_x - 2)) != 0;
+        let color = match (on_face, on_glyph, pressed[key]) {
+            (false, ..) => background,
+            (true, true, false) => foreground,
+            (true, true, true) => background,
+            (true, false, false) => face,
+            (true, false, true) => foreground,
+        };
+        out.copy_from_slice(&[color.r, color.g, color.b, color.a]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_follow_keypad_layout() {
+        assert_eq!(key_at(0, 0), Some(0x1));
+        assert_eq!(key_at(PANEL_WIDTH as i32 - 1, 0), Some(0xc));
+        assert_eq!(key_at(9, 25), Some(0x0));
+        assert_eq!(key_at(31, 31), Some(0xf));
+        assert_eq!(key_at(-1, 5), None);
+        assert_eq!(key_at(5, HEIGHT as i32), None);
+    }
+
+    #[test]
+    fn pressed_keys_are_inverted() {
+        let palette = Palette::default();
+        let mut rgba = vec![0; PANEL_WIDTH * HEIGHT * BYTES_PER_PIXEL];
+        let pixel = |rgba: &[u8], x: usize, y: usize| {
+            let i = (y * PANEL_WIDTH + x) * BYTES_PER_PIXEL;
+            rgba[i..i + BYTES_PER_PIXEL].to_vec()
+        };
+        let white = vec![255, 255, 255, 255];
+        let black = vec![0, 0, 0, 255];
+
+        // Top left pixel of the "1" glyph is off, the one to its right is on.
+        draw_panel(&mut rgba, &[false; 16], &palette);
+        assert_eq!(pixel(&rgba, 0, 0), black);
+        assert_eq!(pixel(&rgba, 4, 2), white);
+        assert_ne!(pixel(&rgba, 2, 2), white);
+
+        let mut pressed = [false; 16];
+        pressed[0x1] = true;
+        draw_panel(&mut rgba, &pressed, &palette);
+        assert_eq!(pixel(&rgba, 0, 0), black);
+        assert_eq!(pixel(&rgba, 4, 2), black);
+        assert_eq!(pixel(&rgba, 2, 2), white);
+        // The border runs along the bottom of the key too.
+        assert_eq!(pixel(&rgba, 1, 6), white);
+        assert_eq!(pixel(&rgba, 1, 7), black);
+    }
+}
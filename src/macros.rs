@@ -0,0 +1,191 @@
+const FRAME_RATE: u32 = 60;
+
+/// Longest macro that can be recorded, in frames.
+pub const MAX_MACRO_FRAMES: usize = 600;
+
+/// Autofire timing: while a turbo key is held, its CHIP-8 key is pressed for the first
+/// half of every cycle and released for the second half.
+pub struct Turbo {
+    cycle: u32,
+    frame: u32,
+}
+
+impl Turbo {
+    /// Autofire at `rate` presses per second, at most one every two frames.
+    pub fn new(rate: u32) -> Self {
+        Turbo {
+            cycle: (FRAME_RATE / rate.max(1)).max(2),
+            frame: 0,
+        }
+    }
+
+    pub fn is_on(&self) -> bool {
+        self.frame < self.cycle / 2
+    }
+
+    pub fn next_frame(&mut self) {
+        self.frame = (self.frame + 1) % self.cycle;
+    }
+
+    /// Restarts the cycle so the next turbo key pressed goes down straight away.
+    pub fn reset(&mut self) {
+        self.frame = 0;
+    }
+}
+
+/// A recorded sequence of keypad states, one per frame, that can be played back.
+pub struct Macro {
+    frames: Vec<[bool; 16]>,
+    recording: bool,
+    stopped_at_limit: bool,
+    playing: Option<usize>,
+}
+
+impl Macro {
+    pub fn new() -> Self {
+        Macro {
+            frames: Vec::new(),
+            recording: false,
+            stopped_at_limit: false,
+            playing: None,
+        }
+    }
+
+    /// Starts recording over the previous macro, or stops recording. Returns whether
+    /// it is now recording. The first toggle after recording stopped at the limit only
+    /// confirms the stop, so it does not throw away the macro just recorded.
+    pub fn toggle_recording(&mut self) -> bool {
+        if self.stopped_at_limit {
+            self.stopped_at_limit = false;
+            return false;
+        }
+        self.recording = !self.recording;
+        if self.recording {
+            self.frames.clear();
+            self.playing = None;
+        }
+        self.recording
+    }
+
+    /// Plays the macro from the start. Does nothing while recording.
+    pub fn play(&mut self) {
+        if !self.recording && !self.frames.is_empty() {
+            self.playing = Some(0);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Keys the macro holds down in the current frame.
+    pub fn keys(&self) -> [bool; 16] {
+        match self.playing {
+            Some(frame) => self.frames[frame],
+            None => [false; 16],
+        }
+    }
+
+    /// Records the keys pressed in the frame just finished when recording, and moves
+    /// playback on to the next frame. Returns true when this frame filled the macro
+    /// and recording stopped.
+    pub fn end_frame(&mut self, pressed: [bool; 16]) -> bool {
+        let mut reached_limit = false;
+        if self.recording {
+            self.frames.push(pressed);
+            if self.frames.len() == MAX_MACRO_FRAMES {
+                self.recording = false;
+                self.stopped_at_limit = true;
+                reached_limit = true;
+            }
+        }
+        self.playing = self
+            .playing
+            .map(|frame| frame + 1)
+            .filter(|&frame| frame < self.frames.len());
+        reached_limit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(pressed: &[usize]) -> [bool; 16] {
+        let mut keys = [false; 16];
+        for &key in pressed {
+            keys[key] = true;
+        }
+        keys
+    }
+
+    #[test]
+    fn turbo_alternates_at_rate() {
+        let mut turbo = Turbo::new(15);
+        let mut states = Vec::new();
+        for _ in 0..8 {
+            states.push(turbo.is_on());
+            turbo.next_frame();
+        }
+        assert_eq!(
+            states,
+            vec![true, true, false, false, true, true, false, false]
+        );
+
+        turbo.reset();
+        assert!(turbo.is_on());
+    }
+
+    #[test]
+    fn turbo_rate_is_capped() {
+        let mut turbo = Turbo::new(60);
+        assert!(turbo.is_on());
+        turbo.next_frame();
+        assert!(!turbo.is_on());
+        turbo.next_frame();
+        assert!(turbo.is_on());
+    }
+
+    #[test]
+    fn macro_plays_back_recorded_frames() {
+        let mut recorded = Macro::new();
+        assert!(recorded.toggle_recording());
+        recorded.end_frame(keys(&[0x5]));
+        recorded.end_frame(keys(&[]));
+        recorded.end_frame(keys(&[0x6]));
+        assert!(!recorded.toggle_recording());
+        assert_eq!(recorded.len(), 3);
+
+        recorded.play();
+        let mut played = Vec::new();
+        for _ in 0..4 {
+            played.push(recorded.keys());
+            recorded.end_frame(keys(&[0x1]));
+        }
+        assert_eq!(
+            played,
+            vec![keys(&[0x5]), keys(&[]), keys(&[0x6]), keys(&[])]
+        );
+        assert_eq!(recorded.len(), 3);
+    }
+
+    #[test]
+    fn recording_stops_at_limit() {
+        let mut recorded = Macro::new();
+        recorded.toggle_recording();
+        let stops: Vec<_> = (0..MAX_MACRO_FRAMES + 10)
+            .map(|_| recorded.end_frame(keys(&[0x2])))
+            .collect();
+        assert_eq!(stops.iter().filter(|&&stopped| stopped).count(), 1);
+        assert!(stops[MAX_MACRO_FRAMES - 1]);
+        assert_eq!(recorded.len(), MAX_MACRO_FRAMES);
+
+        assert!(!recorded.toggle_recording());
+        assert_eq!(recorded.len(), MAX_MACRO_FRAMES);
+        recorded.play();
+        assert_eq!(recorded.keys(), keys(&[0x2]));
+
+        assert!(recorded.toggle_recording());
+        assert_eq!(recorded.len(), 0);
+    }
+}
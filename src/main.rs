@@ -1,29 +1,68 @@
 extern crate sdl2;
 
 mod audio;
+mod condition;
+mod config;
+mod crash;
+mod dap;
+mod debug_window;
+mod debugger;
+mod filter;
+mod font;
+mod gamepad;
+mod gdb;
 mod graphics;
+mod history;
 mod input;
 mod instruction;
+mod keymap;
+mod keypad;
+mod macros;
+mod memory_editor;
+mod palette;
+mod recorder;
+mod screenshot;
+mod server;
+mod state;
+mod symbols;
 mod timer;
+mod trace;
 mod types;
 mod util;
 mod vm;
+mod wav;
 
+use config::*;
 use std::env;
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 2 || args[1] == "-h" {
-        print_usage();
-        return;
-    }
+    let args: Vec<String> = env::args().skip(1).collect();
+    let config = match Config::from_args(&args) {
+        Ok(config) => config,
+        Err(ConfigError::HelpRequested) => {
+            print_usage();
+            return;
+        }
+        Err(err) => {
+            println!("{}", err);
+            print_usage();
+            return;
+        }
+    };
 
-    let sdl_context = sdl2::init().unwrap();
-    let mut vm = vm::VirtualMachine::new(&sdl_context);
-    let rom_path = &args[1];
-    vm.run(rom_path.to_string());
+    match config.headless_frames().unwrap() {
+        Some(frames) => {
+            let mut vm = vm::VirtualMachine::new(None, &config);
+            vm.run_headless(config.rom_path.clone(), frames);
+        }
+        None => {
+            let sdl_context = sdl2::init().unwrap();
+            let mut vm = vm::VirtualMachine::new(Some(&sdl_context), &config);
+            vm.run(config.rom_path.clone());
+        }
+    }
 }
 
 fn print_usage() {
-    println!("Usage: rust_chip8 rom_file")
+    println!("{}", usage())
 }
@@ -0,0 +1,272 @@
+use crate::debugger::*;
+use crate::instruction::*;
+use crate::state::MEMORY_SIZE;
+use crate::types::*;
+
+/// Bytes shown on each row of the memory view.
+pub const BYTES_PER_ROW: usize = 16;
+const MEMORY_ROWS: usize = MEMORY_SIZE / BYTES_PER_ROW;
+/// Where the view starts: the first row of the ROM.
+const START_ROW: usize = 0x200 / BYTES_PER_ROW;
+
+/// A byte of memory or a register that can be edited while the machine is paused.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Field {
+    Memory(usize),
+    Register(Register),
+}
+
+impl Field {
+    /// How many hex digits the field is shown and typed with.
+    pub fn digits(self) -> usize {
+        match self {
+            Field::Memory(_) => 2,
+            Field::Register(register) => register.size() * 2,
+        }
+    }
+
+    fn read(self, target: &dyn Target) -> Word {
+        match self {
+            Field::Memory(address) => target.read_memory(address, 1)[0] as Word,
+            Field::Register(register) => target.read_register(register),
+        }
+    }
+
+    /// Returns false if the target would not take the value.
+    fn write(self, target: &mut dyn Target, value: Word) -> bool {
+        match self {
+            Field::Memory(address) => target.write_memory(address, &[value as u8]),
+            Field::Register(register) => target.write_register(register, value),
+        }
+    }
+}
+
+/// A value as it was before an edit.
+struct Edit {
+    field: Field,
+    old: Word,
+}
+
+/// The state of the debug window's memory view: which rows are shown, which field is
+/// selected and what has been typed into it. Edits are kept so they can be undone.
+pub struct MemoryEditor {
+    top_row: usize,
+    visible_rows: usize,
+    selected: Option<Field>,
+    typed: String,
+    edits: Vec<Edit>,
+}
+
+impl MemoryEditor {
+    pub fn new(visible_rows: usize) -> Self {
+        MemoryEditor {
+            top_row: START_ROW,
+            visible_rows,
+            selected: None,
+            typed: String::new(),
+            edits: Vec::new(),
+        }
+    }
+
+    /// Address of the first byte shown.
+    pub fn top_address(&self) -> usize {
+        self.top_row * BYTES_PER_ROW
+    }
+
+    pub fn selected(&self) -> Option<Field> {
+        self.selected
+    }
+
+    /// Digits typed into the selected field but not yet written.
+    pub fn typed(&self) -> &str {
+        &self.typed
+    }
+
+    pub fn scroll(&mut self, rows: isize) {
+        let last = MEMORY_ROWS - self.visible_rows;
+        self.top_row = (self.top_row as isize + rows).max(0).min(last as isize) as usize;
+    }
+
+    pub fn scroll_page(&mut self, pages: isize) {
+        self.scroll(pages * self.visible_rows as isize);
+    }
+
+    /// Scrolls `address` into view, a few rows down from the top if it was not already.
+    pub fn show(&mut self, address: usize) {
+        let row = address / BYTES_PER_ROW;
+        if row < self.top_row || row >= self.top_row + self.visible_rows {
+            self.top_row = row;
+            self.scroll(-(self.visible_rows as isize / 4));
+        }
+    }
+
+    /// Selects a field to type into, dropping anything typed into the last one.
+    pub fn select(&mut self, field: Option<Field>) {
+        self.selected = field;
+        self.typed.clear();
+    }
+
+    /// Moves the selected byte by `offset` bytes.
+    pub fn move_cursor(&mut self, offset: isize) {
+        if let Some(Field::Memory(address)) = self.selected {
+            let address = (address as isize + offset)
+                .max(0)
+                .min(MEMORY_SIZE as isize - 1);
+            self.select(Some(Field::Memory(address as usize)));
+            self.show(address as usize);
+        }
+    }
+
+    /// Types a hex digit into the selected field, writing it once all its digits are
+    /// typed. Edits are only made while the machine is halted; returns false if the
+    /// digit was ignored.
+    pub fn type_digit(&mut self, digit: u8, target: &mut dyn Target) -> bool {
+        let field = match self.selected {
+            Some(field) if target.debugger().is_halted() => field,
+            _ => return false,
+        };
+        self.typed
+            .push(std::char::from_digit(digit as u32, 16).unwrap());
+        if self.typed.len() == field.digits() {
+            self.commit(target);
+        }
+        true
+    }
+
+    /// Writes what has been typed so far, then moves on to the next byte if a byte was
+    /// edited.
+    pub fn commit(&mut self, target: &mut dyn Target) {
+        let field = match self.selected {
+            Some(field) if !self.typed.is_empty() => field,
+            _ => return,
+        };
+        let value = Word::from_str_radix(&self.typed, 16).unwrap();
+        self.typed.clear();
+        let old = field.read(target);
+        if !field.write(target, value) {
+            return;
+        }
+        self.edits.push(Edit { field, old });
+        if let Field::Memory(_) = field {
+            self.move_cursor(1);
+        }
+    }
+
+    /// Drops what has been typed, or the selection if nothing has.
+    pub fn cancel(&mut self) {
+        if self.typed.is_empty() {
+            self.selected = None;
+        }
+        self.typed.clear();
+    }
+
+    /// Puts back the value changed by the last edit and selects it. Returns false if
+    /// there is nothing to undo or the machine is running.
+    pub fn undo(&mut self, target: &mut dyn Target) -> bool {
+        if !target.debugger().is_halted() {
+            return false;
+        }
+        match self.edits.pop() {
+            Some(edit) => {
+                edit.field.write(target, edit.old);
+                self.select(Some(edit.field));
+                if let Field::Memory(address) = edit.field {
+                    self.show(address);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// How many bytes from I the instruction at PC uses, so they can be highlighted; one
+/// byte for instructions that do not use I.
+pub fn index_length(instruction: Option<Instruction>, target: &dyn Target) -> usize {
+    match instruction {
+        Some(Instruction::DrwVxVy { n, .. }) => n as usize,
+        Some(Instruction::LoadBVx { .. }) => 3,
+        Some(Instruction::StoreVxArray { vx }) | Some(Instruction::ReadVxArray { vx }) => {
+            vx as usize + 1
+        }
+        Some(Instruction::LoadAudioPattern) => 16,
+        _ => 1,
+    }
+    .max(1)
+    .min(MEMORY_SIZE.saturating_sub(target.read_register(Register::I) as usize))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::debugger::testing::TestTarget;
+
+    fn halted_target() -> TestTarget {
+        let mut target = TestTarget::new();
+        target.debugger.halt(StopReason::Interrupt);
+        target
+    }
+
+    #[test]
+    fn typed_bytes_are_written_and_undone() {
+        let mut target = halted_target();
+        target.memory[0x300] = 0x11;
+        let mut editor = MemoryEditor::new(20);
+        editor.select(Some(Field::Memory(0x300)));
+
+        for &digit in &[0xa, 0xb, 0xc] {
+            assert!(editor.type_digit(digit, &mut target));
+        }
+        assert_eq!(target.memory[0x300..0x302], [0xab, 0x00]);
+        assert_eq!(editor.selected(), Some(Field::Memory(0x301)));
+        assert_eq!(editor.typed(), "c");
+        editor.commit(&mut target);
+        assert_eq!(target.memory[0x301], 0x0c);
+
+        assert!(editor.undo(&mut target));
+        assert!(editor.undo(&mut target));
+        assert_eq!(target.memory[0x300..0x302], [0x11, 0x00]);
+        assert_eq!(editor.selected(), Some(Field::Memory(0x300)));
+        assert!(!editor.undo(&mut target));
+    }
+
+    #[test]
+    fn registers_are_edited_only_while_halted() {
+        let mut target = TestTarget::new();
+        let mut editor = MemoryEditor::new(20);
+        editor.select(Some(Field::Register(Register::I)));
+        assert!(!editor.type_digit(3, &mut target));
+
+        target.debugger.halt(StopReason::Interrupt);
+        for &digit in &[0, 3, 4, 0] {
+            editor.type_digit(digit, &mut target);
+        }
+        assert_eq!(target.read_register(Register::I), 0x340);
+        assert_eq!(editor.selected(), Some(Field::Register(Register::I)));
+
+        editor.select(Some(Field::Register(Register::Sp)));
+        for &digit in &[0, 0, 1, 1] {
+            editor.type_digit(digit, &mut target);
+        }
+        assert_eq!(target.read_register(Register::Sp), 0);
+        assert!(editor.undo(&mut target));
+        assert_eq!(target.read_register(Register::I), 0);
+    }
+
+    #[test]
+    fn view_follows_the_cursor() {
+        let mut editor = MemoryEditor::new(20);
+        assert_eq!(editor.top_address(), 0x200);
+        editor.scroll(-100);
+        assert_eq!(editor.top_address(), 0);
+        editor.scroll_page(100);
+        assert_eq!(editor.top_address(), MEMORY_SIZE - 20 * BYTES_PER_ROW);
+
+        editor.select(Some(Field::Memory(0x10)));
+        editor.move_cursor(BYTES_PER_ROW as isize);
+        assert_eq!(editor.selected(), Some(Field::Memory(0x20)));
+        assert_eq!(editor.top_address(), 0);
+        editor.move_cursor(-0x100);
+        assert_eq!(editor.selected(), Some(Field::Memory(0)));
+    }
+}
@@ -0,0 +1,138 @@
+use sdl2::pixels::Color;
+
+/// Display colors indexed by pixel value: background, plane 1, plane 2 and both planes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Palette {
+    pub name: String,
+    pub colors: [Color; 4],
+}
+
+struct Theme {
+    name: &'static str,
+    colors: [(u8, u8, u8); 4],
+}
+
+const THEMES: [Theme; 6] = [
+    Theme {
+        name: "classic",
+        colors: [(0, 0, 0), (255, 255, 255), (170, 170, 170), (85, 85, 85)],
+    },
+    Theme {
+        name: "green",
+        colors: [(8, 24, 8), (51, 255, 102), (26, 153, 51), (170, 255, 187)],
+    },
+    Theme {
+        name: "amber",
+        colors: [(24, 12, 0), (255, 176, 0), (178, 102, 0), (255, 221, 128)],
+    },
+    Theme {
+        name: "lcd",
+        colors: [(155, 188, 15), (15, 56, 15), (48, 98, 48), (139, 172, 15)],
+    },
+    Theme {
+        name: "high-contrast",
+        colors: [(0, 0, 0), (255, 255, 0), (0, 255, 255), (255, 255, 255)],
+    },
+    // Okabe-Ito colors, distinguishable with the common forms of color blindness.
+    Theme {
+        name: "colorblind",
+        colors: [(0, 0, 0), (230, 159, 0), (86, 180, 233), (240, 228, 66)],
+    },
+];
+
+impl Palette {
+    pub fn from_theme(name: &str) -> Option<Palette> {
+        THEMES
+            .iter()
+            .find(|theme| theme.name == name)
+            .map(Palette::from)
+    }
+
+    pub fn theme_names() -> Vec<&'static str> {
+        THEMES.iter().map(|theme| theme.name).collect()
+    }
+
+    /// The built-in theme following this palette, wrapping around. Custom palettes
+    /// cycle back to the first theme.
+    pub fn next_theme(&self) -> Palette {
+        let next = match THEMES.iter().position(|theme| theme.name == self.name) {
+            Some(i) => (i + 1) % THEMES.len(),
+            None => 0,
+        };
+        Palette::from(&THEMES[next])
+    }
+
+    pub fn set_background(&mut self, color: Color) {
+        self.colors[0] = color;
+        self.name = String::from("custom");
+    }
+
+    pub fn set_foreground(&mut self, color: Color) {
+        self.colors[1] = color;
+        self.name = String::from("custom");
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::from(&THEMES[0])
+    }
+}
+
+impl From<&Theme> for Palette {
+    fn from(theme: &Theme) -> Self {
+        let mut colors = [Color::RGB(0, 0, 0); 4];
+        for (color, &(r, g, b)) in colors.iter_mut().zip(theme.colors.iter()) {
+            *color = Color::RGB(r, g, b);
+        }
+        Palette {
+            name: String::from(theme.name),
+            colors,
+        }
+    }
+}
+
+/// Parses a color written as `RRGGBB`, optionally prefixed with `#`.
+pub fn parse_color(text: &str) -> Option<Color> {
+    let hex = text.trim_start_matches('#');
+    if hex.len() != 6 {
+        return None;
+    }
+    let value = u32::from_str_radix(hex, 16).ok()?;
+    Some(Color::RGB(
+        (value >> 16) as u8,
+        (value >> 8) as u8,
+        value as u8,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_color_with_and_without_hash() {
+        assert_eq!(parse_color("ff8000"), Some(Color::RGB(255, 128, 0)));
+        assert_eq!(parse_color("#0A0b0C"), Some(Color::RGB(10, 11, 12)));
+    }
+
+    #[test]
+    fn parse_color_rejects_bad_input() {
+        assert_eq!(parse_color("fff"), None);
+        assert_eq!(parse_color("gg0000"), None);
+    }
+
+    #[test]
+    fn next_theme_wraps_around() {
+        let last = Palette::from_theme("colorblind").unwrap();
+        assert_eq!(last.next_theme().name, "classic");
+    }
+
+    #[test]
+    fn custom_palette_cycles_to_first_theme() {
+        let mut palette = Palette::from_theme("amber").unwrap();
+        palette.set_foreground(Color::RGB(1, 2, 3));
+        assert_eq!(palette.name, "custom");
+        assert_eq!(palette.next_theme().name, "classic");
+    }
+}
@@ -0,0 +1,247 @@
+use crate::screenshot::*;
+use snafu::{ResultExt, Snafu};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Frames per second of the emulated display.
+const FRAME_RATE: u64 = 60;
+/// Shortest delay written for a GIF frame, in hundredths of a second. Most viewers show
+/// frames with shorter delays for a tenth of a second, slowing the animation down.
+const MIN_DELAY: u64 = 2;
+/// Longest delay a GIF frame can hold. Frames shown for longer are written several times.
+const MAX_DELAY: u64 = u16::MAX as u64;
+
+#[derive(Debug, Snafu)]
+pub enum RecordError {
+    #[snafu(display("Could not create {}: {}", path.display(), source))]
+    CreateOutput {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[snafu(display("Could not write {}: {}", path.display(), source))]
+    EncodeGif {
+        path: PathBuf,
+        source: gif::EncodingError,
+    },
+    #[snafu(display("Could not write {}: {}", path.display(), source))]
+    WriteFrame {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[snafu(display("{}", source))]
+    SaveFrame { source: ScreenshotError },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FrameFormat {
+    Png,
+    Ppm,
+}
+
+/// Encodes displayed frames into an animated GIF. Runs of identical frames are
+/// stored once with a longer delay, and frames shown for less than `MIN_DELAY` are
+/// dropped, with the next frame shown from when they started.
+pub struct GifRecorder {
+    path: PathBuf,
+    encoder: gif::Encoder<BufWriter<File>>,
+    width: usize,
+    height: usize,
+    pending: Option<Vec<u8>>,
+    pending_start: u64,
+    frames: u64,
+}
+
+impl GifRecorder {
+    pub fn create(path: &Path, width: usize, height: usize) -> Result<Self, RecordError> {
+        let file = File::create(path).context(CreateOutput { path })?;
+        let mut encoder = gif::Encoder::new(BufWriter::new(file), width as u16, height as u16, &[])
+            .context(EncodeGif { path })?;
+        encoder
+            .set_repeat(gif::Repeat::Infinite)
+            .context(EncodeGif { path })?;
+
+        Ok(GifRecorder {
+            path: path.to_path_buf(),
+            encoder,
+            width,
+            height,
+            pending: None,
+            pending_start: 0,
+            frames: 0,
+        })
+    }
+
+    /// Adds one 60Hz frame of RGBA pixels, which must match the recording's size.
+    pub fn add_frame(&mut self, rgba: &[u8]) -> Result<(), RecordError> {
+        if self.pending.as_deref() != Some(rgba) {
+            if self.pending_delay() >= MIN_DELAY {
+                self.write_pending()?;
+                self.pending_start = self.frames;
+            }
+            self.pending = Some(rgba.to_vec());
+        }
+        self.frames += 1;
+
+        Ok(())
+    }
+
+    /// Writes the last frame and closes the file.
+    pub fn finish(mut self) -> Result<PathBuf, RecordError> {
+        self.write_pending()?;
+        Ok(self.path.clone())
+    }
+
+    fn write_pending(&mut self) -> Result<(), RecordError> {
+        if let Some(mut rgba) = self.pending.take() {
+            let mut frame =
+                gif::Frame::from_rgba_speed(self.width as u16, self.height as u16, &mut rgba, 10);
+            let path = &self.path;
+            let mut remaining = self.pending_delay().max(MIN_DELAY);
+            while remaining > 0 {
+                let delay = if remaining > MAX_DELAY {
+                    MAX_DELAY.min(remaining - MIN_DELAY)
+                } else {
+                    remaining
+                };
+                frame.delay = delay as u16;
+                self.encoder
+                    .write_frame(&frame)
+                    .context(EncodeGif { path })?;
+                remaining -= delay;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// How long the pending frame has been shown, in hundredths of a second.
+    fn pending_delay(&self) -> u64 {
+        centiseconds(self.frames) - centiseconds(self.pending_start)
+    }
+}
+
+/// Time at the start of a frame in the GIF's hundredths of a second, rounded to stay
+/// in step with 60Hz.
+fn centiseconds(frame: u64) -> u64 {
+    (frame * 100 + FRAME_RATE / 2) / FRAME_RATE
+}
+
+/// Writes each displayed frame to a numbered image file, for external video tools.
+pub struct FrameDump {
+    directory: PathBuf,
+    format: FrameFormat,
+    count: u64,
+}
+
+impl FrameDump {
+    pub fn create(directory: &Path, format: FrameFormat) -> Result<Self, RecordError> {
+        fs::create_dir_all(directory).context(CreateOutput { path: directory })?;
+        Ok(FrameDump {
+            directory: directory.to_path_buf(),
+            format,
+            count: 0,
+        })
+    }
+
+    pub fn add_frame(
+        &mut self,
+        rgba: &[u8],
+        width: usize,
+        height: usize,
+    ) -> Result<(), RecordError> {
+        self.count += 1;
+        match self.format {
+            FrameFormat::Png => {
+                let path = self.directory.join(format!("frame-{:06}.png", self.count));
+                save_png(&path, rgba, width, height).context(SaveFrame)
+            }
+            FrameFormat::Ppm => {
+                let path = self.directory.join(format!("frame-{:06}.ppm", self.count));
+                let file = File::create(&path).context(CreateOutput { path: &path })?;
+                write_ppm(&mut BufWriter::new(file), rgba, width, height)
+                    .context(WriteFrame { path: &path })
+            }
+        }
+    }
+}
+
+/// Writes RGBA pixels as a binary PPM (P6) image, dropping alpha.
+pub fn write_ppm<W: Write>(
+    out: &mut W,
+    rgba: &[u8],
+    width: usize,
+    height: usize,
+) -> std::io::Result<()> {
+    write!(out, "P6\n{} {}\n255\n", width, height)?;
+    for pixel in rgba.chunks_exact(4) {
+        out.write_all(&pixel[0..3])?;
+    }
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn centiseconds_track_sixty_hertz() {
+        let delays: Vec<u64> = (0..6)
+            .map(|frame| centiseconds(frame + 1) - centiseconds(frame))
+            .collect();
+        assert_eq!(delays, vec![2, 1, 2, 2, 1, 2]);
+        assert_eq!(centiseconds(60), 100);
+    }
+
+    #[test]
+    fn gif_frames_are_never_shorter_than_viewers_show() {
+        let path = std::env::temp_dir().join("rust_chip8_min_delay.gif");
+        let mut recorder = GifRecorder::create(&path, 1, 1).unwrap();
+        for frame in 0..60 {
+            let shade = if frame % 2 == 0 { 0 } else { 255 };
+            recorder.add_frame(&[shade, shade, shade, 255]).unwrap();
+        }
+        recorder.finish().unwrap();
+
+        let mut decoder = gif::DecodeOptions::new()
+            .read_info(File::open(&path).unwrap())
+            .unwrap();
+        let mut delays = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            delays.push(frame.delay as u64);
+        }
+        fs::remove_file(&path).unwrap();
+
+        assert!(delays.iter().all(|&delay| delay >= MIN_DELAY), "{:?}", delays);
+        assert!(delays.len() > 1);
+        assert_eq!(delays.iter().sum::<u64>(), 100);
+    }
+
+    #[test]
+    fn long_gif_frames_are_split() {
+        let path = std::env::temp_dir().join("rust_chip8_max_delay.gif");
+        let mut recorder = GifRecorder::create(&path, 1, 1).unwrap();
+        for _ in 0..40_000 {
+            recorder.add_frame(&[0, 0, 0, 255]).unwrap();
+        }
+        recorder.finish().unwrap();
+
+        let mut decoder = gif::DecodeOptions::new()
+            .read_info(File::open(&path).unwrap())
+            .unwrap();
+        let mut delays = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            delays.push(frame.delay as u64);
+        }
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(delays, vec![MAX_DELAY, centiseconds(40_000) - MAX_DELAY]);
+    }
+
+    #[test]
+    fn write_ppm_drops_alpha() {
+        let mut out = Vec::new();
+        write_ppm(&mut out, &[1, 2, 3, 255, 4, 5, 6, 255], 2, 1).unwrap();
+        assert_eq!(out, b"P6\n2 1\n255\n\x01\x02\x03\x04\x05\x06".to_vec());
+    }
+}
@@ -0,0 +1,65 @@
+use crate::graphics::*;
+use crate::util::*;
+use snafu::{ResultExt, Snafu};
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+/// Nearest-neighbor scale of the scaled screenshot when no filter is active.
+const SCREENSHOT_SCALE: usize = 8;
+
+#[derive(Debug, Snafu)]
+pub enum ScreenshotError {
+    #[snafu(display("Could not create directory {}: {}", path.display(), source))]
+    CreateDirectory {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[snafu(display("Could not create {}: {}", path.display(), source))]
+    CreateFile {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[snafu(display("Could not write {}: {}", path.display(), source))]
+    EncodePng {
+        path: PathBuf,
+        source: png::EncodingError,
+    },
+}
+
+/// Writes RGBA pixels to a PNG file.
+pub fn save_png(
+    path: &Path,
+    rgba: &[u8],
+    width: usize,
+    height: usize,
+) -> Result<(), ScreenshotError> {
+    let file = File::create(path).context(CreateFile { path })?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width as u32, height as u32);
+    encoder.set_color(png::ColorType::RGBA);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().context(EncodePng { path })?;
+    writer.write_image_data(rgba).context(EncodePng { path })?;
+
+    Ok(())
+}
+
+/// Saves the current display into `directory` twice, at the native 64x32 and scaled
+/// as shown in the window, both in the active palette. Files are named after the
+/// current time; returns the paths written.
+pub fn save_screenshot(
+    graphics: &Graphics,
+    directory: &Path,
+) -> Result<Vec<PathBuf>, ScreenshotError> {
+    fs::create_dir_all(directory).context(CreateDirectory { path: directory })?;
+    let name = format!("chip8-{}", timestamp());
+
+    let native_path = directory.join(format!("{}.png", name));
+    save_png(&native_path, graphics.framebuffer(), WIDTH, HEIGHT)?;
+
+    let scaled_path = directory.join(format!("{}-scaled.png", name));
+    let (frame, width, height) = graphics.scaled_frame(SCREENSHOT_SCALE);
+    save_png(&scaled_path, &frame, width, height)?;
+
+    Ok(vec![native_path, scaled_path])
+}
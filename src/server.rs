@@ -0,0 +1,86 @@
+use snafu::{ResultExt, Snafu};
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+
+#[derive(Debug, Snafu)]
+pub enum ServerError {
+    #[snafu(display("Could not listen on {}: {}", address, source))]
+    Listen {
+        address: SocketAddr,
+        source: io::Error,
+    },
+}
+
+/// A TCP server for one debugger client at a time. Everything is polled from the VM
+/// loop without blocking, except for writes.
+pub struct DebugServer {
+    listener: TcpListener,
+    client: Option<TcpStream>,
+    pub buffer: Vec<u8>,
+}
+
+impl DebugServer {
+    pub fn listen(address: SocketAddr) -> Result<DebugServer, ServerError> {
+        let listener = TcpListener::bind(address).context(Listen { address })?;
+        listener.set_nonblocking(true).context(Listen { address })?;
+        Ok(DebugServer {
+            listener,
+            client: None,
+            buffer: Vec::new(),
+        })
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.client.is_some()
+    }
+
+    /// Accepts a waiting client, returning its address.
+    pub fn accept(&mut self) -> Option<SocketAddr> {
+        let (stream, address) = self.listener.accept().ok()?;
+        if let Err(err) = stream.set_nonblocking(true) {
+            println!("Could not accept connection from {}: {}", address, err);
+            return None;
+        }
+        self.client = Some(stream);
+        self.buffer.clear();
+        Some(address)
+    }
+
+    pub fn disconnect(&mut self) {
+        self.client = None;
+    }
+
+    /// Appends everything that has arrived to `buffer`. Fails once the client has gone.
+    pub fn receive(&mut self) -> io::Result<()> {
+        let client = match &mut self.client {
+            Some(client) => client,
+            None => return Err(io::ErrorKind::NotConnected.into()),
+        };
+        let mut chunk = [0; 1024];
+        loop {
+            match client.read(&mut chunk) {
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(count) => self.buffer.extend_from_slice(&chunk[..count]),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Writes to the client, blocking until everything is sent. The client is dropped
+    /// if that fails.
+    pub fn send(&mut self, bytes: &[u8]) -> io::Result<()> {
+        let client = match &mut self.client {
+            Some(client) => client,
+            None => return Err(io::ErrorKind::NotConnected.into()),
+        };
+        let result = client
+            .set_nonblocking(false)
+            .and_then(|_| client.write_all(bytes))
+            .and_then(|_| client.set_nonblocking(true));
+        if result.is_err() {
+            self.client = None;
+        }
+        result
+    }
+}
@@ -0,0 +1,138 @@
+use crate::debugger::*;
+use crate::graphics::*;
+use crate::types::*;
+use snafu::{OptionExt, ResultExt, Snafu};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Start of a save state file, with the format version.
+const MAGIC: &[u8; 8] = b"CHIP8ST1";
+pub const MEMORY_SIZE: usize = 4096;
+pub const STACK_SIZE: usize = 16;
+const STATE_SIZE: usize =
+    MAGIC.len() + REGISTER_COUNT * 2 + STACK_SIZE * 2 + MEMORY_SIZE + WIDTH * HEIGHT;
+
+#[derive(Debug, Snafu)]
+pub enum StateError {
+    #[snafu(display("Could not write {}: {}", path.display(), source))]
+    WriteFile {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[snafu(display("Could not read {}: {}", path.display(), source))]
+    ReadFile {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[snafu(display("{} is not a CHIP-8 save state", path.display()))]
+    BadFormat { path: PathBuf },
+}
+
+/// Everything that makes up a running machine, apart from the keys held.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MachineState {
+    /// Indexed by `Register::index`.
+    pub registers: [Word; REGISTER_COUNT],
+    pub stack: Vec<Word>,
+    pub memory: Vec<u8>,
+    pub vram: Vram,
+}
+
+impl MachineState {
+    /// Saves the state as the registers, stack, memory and VRAM one after the other,
+    /// with words little-endian.
+    pub fn save(&self, path: &Path) -> Result<(), StateError> {
+        fs::write(path, self.to_bytes()).context(WriteFile { path })
+    }
+
+    pub fn load(path: &Path) -> Result<MachineState, StateError> {
+        let bytes = fs::read(path).context(ReadFile { path })?;
+        MachineState::from_bytes(&bytes).context(BadFormat { path })
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        for word in self.registers.iter().chain(&self.stack) {
+            bytes.extend_from_slice(&word.to_le_bytes());
+        }
+        bytes.extend_from_slice(&self.memory);
+        for row in self.vram.iter() {
+            bytes.extend_from_slice(row);
+        }
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<MachineState> {
+        if bytes.len() != STATE_SIZE || !bytes.starts_with(MAGIC) {
+            return None;
+        }
+        let (words, rest) = bytes[MAGIC.len()..].split_at((REGISTER_COUNT + STACK_SIZE) * 2);
+        let (memory, pixels) = rest.split_at(MEMORY_SIZE);
+        let mut words = words
+            .chunks_exact(2)
+            .map(|word| Word::from_le_bytes([word[0], word[1]]));
+        let mut registers = [0; REGISTER_COUNT];
+        for register in registers.iter_mut() {
+            *register = words.next()?;
+        }
+        if registers[Register::Sp.index()] as usize > STACK_SIZE {
+            return None;
+        }
+        let mut vram = [[0; WIDTH]; HEIGHT];
+        for (row, pixels) in vram.iter_mut().zip(pixels.chunks_exact(WIDTH)) {
+            row.copy_from_slice(pixels);
+        }
+        Some(MachineState {
+            registers,
+            stack: words.collect(),
+            memory: memory.to_vec(),
+            vram,
+        })
+    }
+
+    pub fn register(&self, register: Register) -> Word {
+        self.registers[register.index()]
+    }
+
+    /// Return addresses of the subroutine calls in progress, innermost first.
+    pub fn call_stack(&self) -> Vec<Word> {
+        let depth = (self.register(Register::Sp) as usize).min(self.stack.len());
+        self.stack[..depth].iter().rev().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saved_state_loads_back() {
+        let mut registers = [0; REGISTER_COUNT];
+        registers[Register::V(3).index()] = 0x42;
+        registers[Register::Pc.index()] = 0x2a4;
+        registers[Register::Sp.index()] = 2;
+        let mut memory = vec![0; MEMORY_SIZE];
+        memory[0xfff] = 0x99;
+        let mut vram = [[0; WIDTH]; HEIGHT];
+        vram[31][63] = 1;
+        let mut stack = vec![0; STACK_SIZE];
+        stack[0] = 0x204;
+        stack[1] = 0x310;
+        let state = MachineState {
+            registers,
+            stack,
+            memory,
+            vram,
+        };
+
+        let bytes = state.to_bytes();
+        assert_eq!(bytes.len(), STATE_SIZE);
+        assert_eq!(MachineState::from_bytes(&bytes), Some(state.clone()));
+        assert_eq!(state.call_stack(), vec![0x310, 0x204]);
+
+        assert_eq!(MachineState::from_bytes(&bytes[1..]), None);
+        let mut bad_stack = bytes;
+        bad_stack[MAGIC.len() + Register::Sp.index() * 2] = 17;
+        assert_eq!(MachineState::from_bytes(&bad_stack), None);
+    }
+}
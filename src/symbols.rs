@@ -0,0 +1,188 @@
+use crate::instruction::*;
+use crate::types::*;
+use snafu::{ResultExt, Snafu};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Snafu)]
+pub enum SymbolError {
+    #[snafu(display("Could not read {}: {}", path.display(), source))]
+    ReadSymbols {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[snafu(display("{} line {}: expected an address and a label, got {:?}", path.display(), line, text))]
+    BadLine {
+        path: PathBuf,
+        line: usize,
+        text: String,
+    },
+}
+
+/// Labels for addresses in a ROM, and the ranges of it that hold data rather than
+/// instructions, as listed in a symbol file.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SymbolMap {
+    labels: BTreeMap<Word, String>,
+    /// First and last address of each data region, which is labelled at its start.
+    data: Vec<(Word, Word)>,
+}
+
+impl SymbolMap {
+    /// Reads a symbol file: one label per line, as a hex address and a name such as
+    /// `24e draw_player`, or a data region as a hex range and a name such as
+    /// `300-30f player_sprite`. Blank lines and lines starting with `#` are skipped.
+    pub fn load(path: &Path) -> Result<SymbolMap, SymbolError> {
+        let text = fs::read_to_string(path).context(ReadSymbols { path })?;
+        SymbolMap::parse(&text).map_err(|(line, text)| SymbolError::BadLine {
+            path: path.to_path_buf(),
+            line,
+            text,
+        })
+    }
+
+    /// Parses a symbol file, or returns the number and text of the first bad line.
+    pub fn parse(text: &str) -> Result<SymbolMap, (usize, String)> {
+        let mut symbols = SymbolMap::default();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let bad_line = || (number + 1, line.to_string());
+            let mut words = line.split_whitespace();
+            let (range, name) = match (words.next(), words.next(), words.next()) {
+                (Some(range), Some(name), None) => (range, name),
+                _ => return Err(bad_line()),
+            };
+            match range.split_once('-') {
+                Some((first, last)) => {
+                    let first = parse_address(first).ok_or_else(bad_line)?;
+                    let last = parse_address(last).filter(|&last| last >= first);
+                    symbols.data.push((first, last.ok_or_else(bad_line)?));
+                    symbols.labels.insert(first, name.to_string());
+                }
+                None => {
+                    let address = parse_address(range).ok_or_else(bad_line)?;
+                    symbols.labels.insert(address, name.to_string());
+                }
+            }
+        }
+        Ok(symbols)
+    }
+
+    pub fn label(&self, address: Word) -> Option<&str> {
+        self.labels.get(&address).map(String::as_str)
+    }
+
+    /// The nearest label at or before `address`, with the distance from it, such as
+    /// `draw_player+4`.
+    pub fn locate(&self, address: Word) -> Option<String> {
+        let (start, label) = self.labels.range(..=address).next_back()?;
+        Some(match address - start {
+            0 => label.clone(),
+            offset => format!("{}+{}", label, offset),
+        })
+    }
+
+    /// The address of a label, or of a hex address such as `24e` or `0x24e`.
+    pub fn resolve(&self, name: &str) -> Option<Word> {
+        self.labels
+            .iter()
+            .find(|(_, label)| *label == name)
+            .map(|(&address, _)| address)
+            .or_else(|| parse_address(name))
+    }
+
+    /// Whether `address` is inside a data region.
+    pub fn is_data(&self, address: Word) -> bool {
+        self.data
+            .iter()
+            .any(|&(first, last)| first <= address && address <= last)
+    }
+
+    /// Disassembles `instruction` with the labels of any address it refers to.
+    pub fn disassemble(&self, instruction: &Instruction) -> String {
+        let (prefix, address) = match *instruction {
+            Instruction::Jp { address } => ("JP", address),
+            Instruction::Call { address } => ("CALL", address),
+            Instruction::LdIFromAddr { address } => ("LD I,", address),
+            Instruction::JpV0PlusAddr { address } => ("JP V0,", address),
+            _ => return instruction.to_string(),
+        };
+        match self.label(address) {
+            Some(label) => format!("{} {}", prefix, label),
+            None => instruction.to_string(),
+        }
+    }
+}
+
+/// The symbol file for a ROM: the ROM's path with a `.sym` extension, if there is one.
+pub fn symbols_beside(rom_path: &Path) -> Option<PathBuf> {
+    let path = rom_path.with_extension("sym");
+    if path.is_file() {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+fn parse_address(text: &str) -> Option<Word> {
+    let digits = text.strip_prefix("0x").unwrap_or(text);
+    Word::from_str_radix(digits, 16)
+        .ok()
+        .filter(|&address| address <= 0xfff)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SYMBOLS: &str = "
+        # pong.sym
+        200 main
+        24e draw_player
+        300-30f player_sprite
+    ";
+
+    #[test]
+    fn labels_and_data_regions() {
+        let symbols = SymbolMap::parse(SYMBOLS).unwrap();
+        assert_eq!(symbols.label(0x24e), Some("draw_player"));
+        assert_eq!(symbols.locate(0x252), Some("draw_player+4".to_string()));
+        assert_eq!(symbols.locate(0x200), Some("main".to_string()));
+        assert_eq!(symbols.locate(0x1fe), None);
+        assert_eq!(symbols.resolve("draw_player"), Some(0x24e));
+        assert_eq!(symbols.resolve("player_sprite"), Some(0x300));
+        assert_eq!(symbols.resolve("0x2a0"), Some(0x2a0));
+        assert_eq!(symbols.resolve("nowhere"), None);
+        assert!(symbols.is_data(0x30f));
+        assert!(!symbols.is_data(0x310));
+        assert!(!symbols.is_data(0x24e));
+    }
+
+    #[test]
+    fn disassembly_uses_labels() {
+        let symbols = SymbolMap::parse(SYMBOLS).unwrap();
+        let text = |opcode| symbols.disassemble(&decode(opcode).unwrap());
+        assert_eq!(text(0x224e), "CALL draw_player");
+        assert_eq!(text(0xa300), "LD I, player_sprite");
+        assert_eq!(text(0xb200), "JP V0, main");
+        assert_eq!(text(0x1202), "JP 0x202");
+        assert_eq!(text(0x6a10), "LD VA, 0x10");
+    }
+
+    #[test]
+    fn bad_lines_are_reported() {
+        assert_eq!(
+            SymbolMap::parse("200 main\n24e\n"),
+            Err((2, "24e".to_string()))
+        );
+        assert_eq!(
+            SymbolMap::parse("310-300 backwards"),
+            Err((1, "310-300 backwards".to_string()))
+        );
+        assert!(SymbolMap::parse("1000 too_far").is_err());
+    }
+}
@@ -20,10 +20,18 @@ impl Timer {
         self.instant = Instant::now();
     }
 
-    pub fn get_value(&mut self) -> u8 {
+    pub fn get_value(&self) -> u8 {
         self.value
     }
 
+    /// Decrements the value once, for timers driven by emulated frames rather than
+    /// wall-clock time.
+    pub fn count_down(&mut self) {
+        if self.value > 0 {
+            self.value -= 1;
+        }
+    }
+
     pub fn tick(&mut self) {
         if self.value == 0 {
             return;
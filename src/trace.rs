@@ -0,0 +1,220 @@
+use crate::debugger::*;
+use crate::instruction::*;
+use crate::symbols::SymbolMap;
+use crate::types::*;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// Which executed instructions go in a trace.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TraceFilter {
+    /// First and last address to trace.
+    pub pc_range: Option<(Word, Word)>,
+    /// Mnemonics to trace, such as `DRW`, or every instruction if empty.
+    pub mnemonics: Vec<String>,
+}
+
+impl TraceFilter {
+    fn accepts(&self, pc: Word, instruction: Option<&Instruction>) -> bool {
+        let in_range = match self.pc_range {
+            Some((first, last)) => first <= pc && pc <= last,
+            None => true,
+        };
+        // Undecodable words are always traced so errors can be seen.
+        let wanted = match instruction {
+            Some(instruction) if !self.mnemonics.is_empty() => self
+                .mnemonics
+                .iter()
+                .any(|mnemonic| mnemonic == instruction.mnemonic()),
+            _ => true,
+        };
+        in_range && wanted
+    }
+}
+
+/// Writes a line per executed instruction: the instruction count, address, opcode,
+/// disassembly and the registers it changed. Labelled addresses get a line with the
+/// label first. With `last` set, lines are kept in a ring buffer of that many and only
+/// written out by `dump`.
+pub struct Trace<W: Write> {
+    out: W,
+    filter: TraceFilter,
+    symbols: SymbolMap,
+    last: Option<usize>,
+    lines: VecDeque<String>,
+    count: u64,
+}
+
+impl Trace<BufWriter<File>> {
+    pub fn create(path: &Path, filter: TraceFilter, last: Option<usize>) -> io::Result<Self> {
+        Ok(Trace::new(
+            BufWriter::new(File::create(path)?),
+            filter,
+            last,
+        ))
+    }
+}
+
+impl<W: Write> Trace<W> {
+    pub fn new(out: W, filter: TraceFilter, last: Option<usize>) -> Self {
+        Trace {
+            out,
+            filter,
+            symbols: SymbolMap::default(),
+            last,
+            lines: VecDeque::new(),
+            count: 0,
+        }
+    }
+
+    pub fn set_symbols(&mut self, symbols: SymbolMap) {
+        self.symbols = symbols;
+    }
+
+    /// Records an executed instruction. `instruction` is `None` if `opcode` did not
+    /// decode, and `changes` has the new values of the registers it changed.
+    pub fn record(
+        &mut self,
+        pc: Word,
+        opcode: Word,
+        instruction: Option<&Instruction>,
+        changes: &[(Register, Word)],
+    ) -> io::Result<()> {
+        self.count += 1;
+        if !self.filter.accepts(pc, instruction) {
+            return Ok(());
+        }
+        let text = match instruction {
+            Some(instruction) => self.symbols.disassemble(instruction),
+            None => "???".to_string(),
+        };
+        let changes: Vec<String> = changes
+            .iter()
+            .map(|(register, value)| {
+                let name = register.name().to_uppercase();
+                format!("{}={:0width$x}", name, value, width = register.size() * 2)
+            })
+            .collect();
+        let line = format!(
+            "{:>10}  {:03x}  {:04x}  {:<16}  {}",
+            self.count,
+            pc,
+            opcode,
+            text,
+            changes.join(" ")
+        );
+        let line = match self.symbols.label(pc) {
+            Some(label) => format!("{}:\n{}", label, line),
+            None => line,
+        };
+        match self.last {
+            Some(last) => {
+                if self.lines.len() == last {
+                    self.lines.pop_front();
+                }
+                self.lines.push_back(line);
+                Ok(())
+            }
+            None => writeln!(self.out, "{}", line.trim_end()),
+        }
+    }
+
+    /// The lines held in the ring buffer, oldest first.
+    pub fn buffered(&self) -> Vec<String> {
+        self.lines.iter().cloned().collect()
+    }
+
+    /// Marks a stop or an error in the trace, first writing out the ring buffer.
+    pub fn dump(&mut self, reason: &str) -> io::Result<()> {
+        for line in self.lines.drain(..) {
+            writeln!(self.out, "{}", line.trim_end())?;
+        }
+        writeln!(self.out, "-- {}", reason)?;
+        self.out.flush()
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An address, opcode and the registers the instruction changed.
+    type Step = (Word, Word, &'static [(Register, Word)]);
+
+    fn run(trace: &mut Trace<Vec<u8>>) {
+        let program: [Step; 4] = [
+            (0x200, 0x6a10, &[(Register::V(10), 0x10)]),
+            (0x202, 0xa300, &[(Register::I, 0x300)]),
+            (0x204, 0xd125, &[(Register::V(15), 1)]),
+            (0x206, 0xe1ff, &[]),
+        ];
+        for (pc, opcode, changes) in program.iter() {
+            let instruction = decode(*opcode).ok();
+            trace
+                .record(*pc, *opcode, instruction.as_ref(), changes)
+                .unwrap();
+        }
+    }
+
+    fn lines(trace: Trace<Vec<u8>>) -> Vec<String> {
+        let text = String::from_utf8(trace.finish().unwrap()).unwrap();
+        text.lines().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn one_line_per_instruction() {
+        let mut trace = Trace::new(Vec::new(), TraceFilter::default(), None);
+        run(&mut trace);
+        assert_eq!(
+            lines(trace),
+            vec![
+                "         1  200  6a10  LD VA, 0x10       VA=10",
+                "         2  202  a300  LD I, 0x300       I=0300",
+                "         3  204  d125  DRW V1, V2, 5     VF=01",
+                "         4  206  e1ff  ???",
+            ]
+        );
+    }
+
+    #[test]
+    fn labels_are_shown() {
+        let mut trace = Trace::new(Vec::new(), TraceFilter::default(), None);
+        trace.set_symbols(SymbolMap::parse("202 load_sprite\n300 sprite").unwrap());
+        run(&mut trace);
+        let lines = lines(trace);
+        assert_eq!(lines[1], "load_sprite:");
+        assert_eq!(lines[2], "         2  202  a300  LD I, sprite      I=0300");
+    }
+
+    #[test]
+    fn filters_by_address_and_mnemonic() {
+        let filter = TraceFilter {
+            pc_range: Some((0x202, 0x2ff)),
+            mnemonics: vec!["LD".to_string()],
+        };
+        let mut trace = Trace::new(Vec::new(), filter, None);
+        run(&mut trace);
+        let lines = lines(trace);
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].contains("LD I, 0x300"));
+        assert!(lines[1].contains("???"));
+    }
+
+    #[test]
+    fn ring_buffer_is_written_on_dump() {
+        let mut trace = Trace::new(Vec::new(), TraceFilter::default(), Some(2));
+        run(&mut trace);
+        trace.dump("Invalid instruction e1ff").unwrap();
+        let lines = lines(trace);
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("         3  204"));
+        assert_eq!(lines[2], "-- Invalid instruction e1ff");
+    }
+}
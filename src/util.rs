@@ -2,6 +2,7 @@ use crate::types::*;
 use std::convert::TryInto;
 use std::fs::File;
 use std::io::Read;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 pub fn low_byte(word: Word) -> Byte {
     word.to_be_bytes()[1]
@@ -31,3 +32,53 @@ pub fn get_file_as_byte_vec(filename: &String) -> Vec<u8> {
 
     buffer
 }
+
+/// The current UTC time as `YYYYMMDD-HHMMSS`, for naming output files.
+pub fn timestamp() -> String {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0);
+    format_timestamp(seconds)
+}
+
+fn format_timestamp(unix_seconds: u64) -> String {
+    let days = (unix_seconds / 86400) as i64;
+    let time = unix_seconds % 86400;
+
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm).
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_timestamp_epoch() {
+        assert_eq!(format_timestamp(0), "19700101-000000");
+    }
+
+    #[test]
+    fn format_timestamp_leap_day() {
+        assert_eq!(format_timestamp(951_827_696), "20000229-123456");
+    }
+}
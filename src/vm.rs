@@ -1,15 +1,34 @@
 use crate::audio::*;
+use crate::config::*;
+use crate::crash::*;
+use crate::dap::*;
+use crate::debug_window::*;
+use crate::debugger::*;
+use crate::gdb::*;
 use crate::graphics::*;
+use crate::history::*;
 use crate::input::*;
 use crate::instruction::*;
+use crate::macros::Turbo;
+use crate::recorder::*;
+use crate::screenshot::*;
+use crate::state::*;
+use crate::symbols::*;
 use crate::timer::*;
+use crate::trace::*;
 use crate::types::*;
 use crate::util::*;
 use rand::prelude::*;
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
 
 const ROM_START: usize = 0x200;
+const RECORDING_SCALE: usize = 4;
+/// Instructions run in each 60Hz frame: 540Hz, the speed of the CPU timer.
+const CYCLES_PER_FRAME: usize = 9;
 
-const FONT_SET: [u8; 80] = [
+pub const FONT_SET: [u8; 80] = [
     0xF0, 0x90, 0x90, 0x90, 0xF0, 0x20, 0x60, 0x20, 0x20, 0x70, 0xF0, 0x10, 0xF0, 0x80, 0xF0, 0xF0,
     0x10, 0xF0, 0x10, 0xF0, 0x90, 0x90, 0xF0, 0x10, 0x10, 0xF0, 0x80, 0xF0, 0x10, 0xF0, 0xF0, 0x80,
     0xF0, 0x90, 0xF0, 0xF0, 0x10, 0x20, 0x40, 0x40, 0xF0, 0x90, 0xF0, 0x90, 0xF0, 0xF0, 0x90, 0xF0,
@@ -25,6 +44,7 @@ pub struct VirtualMachine {
     sound_timer: Timer,
     delay_timer: Timer,
     cpu_timer: Timer,
+    frame_timer: Timer,
     stack: Vec<u16>,
     stack_pointer: Word,
     rng: ThreadRng,
@@ -34,12 +54,35 @@ pub struct VirtualMachine {
     done: bool,
     waiting_for_key: bool,
     key_register: u8,
+    key_wait: KeyWait,
     should_draw: bool,
+    screenshot_dir: PathBuf,
+    gif_recorder: Option<GifRecorder>,
+    frame_dump: Option<FrameDump>,
+    audio_capture: Option<AudioCapture<BufWriter<File>>>,
+    debugger: Debugger,
+    gdb: Option<GdbStub>,
+    dap: Option<DapServer>,
+    /// Undo information for reverse stepping, kept while a debugger can connect.
+    history: Option<History>,
+    trace: Option<Trace<BufWriter<File>>>,
+    /// The last instructions run, for crash reports.
+    recent: Trace<io::Sink>,
+    crash_dir: PathBuf,
+    load_state: Option<PathBuf>,
+    /// Opened the first time it is shown. Taken out while it handles events, as they
+    /// can edit the machine.
+    debug_window: Option<DebugWindow>,
+    /// None when running headless.
+    sdl_context: Option<sdl2::Sdl>,
+    symbols: SymbolMap,
 }
 
 impl VirtualMachine {
-    pub fn new(sdl_context: &sdl2::Sdl) -> VirtualMachine {
-        VirtualMachine {
+    /// Creates the machine with its window and sound device, or without them if there
+    /// is no SDL context.
+    pub fn new(sdl_context: Option<&sdl2::Sdl>, config: &Config) -> VirtualMachine {
+        let mut vm = VirtualMachine {
             memory: vec![0; 4096],
             registers: vec![0; 16],
             index: 0,
@@ -47,84 +90,475 @@ impl VirtualMachine {
             delay_timer: Timer::new(60),
             sound_timer: Timer::new(60),
             cpu_timer: Timer::new(540),
+            frame_timer: Timer::new(60),
             stack: vec![0; 16],
             stack_pointer: 0,
             rng: thread_rng(),
-            graphics: Graphics::new(sdl_context),
-            audio: Audio::new(sdl_context),
-            input: Input::new(sdl_context),
+            graphics: Graphics::new(
+                sdl_context,
+                config.palette().unwrap(),
+                config.display_mode().unwrap(),
+                config.filters().unwrap(),
+                config.keypad,
+            ),
+            audio: Audio::new(sdl_context, config.tone_settings().unwrap()),
+            input: Input::new(
+                sdl_context,
+                config.keymap().unwrap(),
+                config.pad_map().unwrap(),
+                Turbo::new(config.turbo_rate().unwrap()),
+            ),
             done: false,
             waiting_for_key: false,
             key_register: 0,
+            key_wait: KeyWait::new(config.key_wait_mode().unwrap()),
             should_draw: false,
+            screenshot_dir: config.screenshot_dir(),
+            gif_recorder: None,
+            frame_dump: None,
+            audio_capture: None,
+            debugger: Debugger::new(),
+            gdb: None,
+            dap: None,
+            history: None,
+            trace: None,
+            recent: Trace::new(io::sink(), TraceFilter::default(), Some(CRASH_TRACE_LENGTH)),
+            crash_dir: config.crash_dir(),
+            load_state: config.load_state.clone(),
+            debug_window: None,
+            sdl_context: sdl_context.cloned(),
+            symbols: SymbolMap::default(),
+        };
+
+        vm.input.set_keypad_enabled(config.keypad);
+        if config.debug_window {
+            vm.open_debug_window();
+        }
+        if let Some(address) = config.gdb_address().unwrap() {
+            match GdbStub::listen(address) {
+                Ok(stub) => {
+                    println!("Waiting for GDB on {}", address);
+                    vm.gdb = Some(stub);
+                    vm.debugger.attach();
+                }
+                Err(err) => println!("{}", err),
+            }
+        }
+        if let Some(address) = config.dap_address().unwrap() {
+            match DapServer::listen(address) {
+                Ok(server) => {
+                    println!("Waiting for a debug adapter client on {}", address);
+                    vm.dap = Some(server);
+                    vm.debugger.attach();
+                }
+                Err(err) => println!("{}", err),
+            }
+        }
+        if vm.gdb.is_some() || vm.dap.is_some() {
+            vm.history = Some(History::new(HISTORY_LENGTH));
+        }
+        if let Some(path) = &config.record_gif {
+            vm.start_recording(path);
+        }
+        if let Some(directory) = &config.dump_frames {
+            match FrameDump::create(directory, config.frame_format().unwrap()) {
+                Ok(dump) => vm.frame_dump = Some(dump),
+                Err(err) => println!("{}", err),
+            }
         }
-    }
 
-    pub fn run(&mut self, rom_path: String) {
-        for i in 0..FONT_SET.len() {
-            self.memory[i] = FONT_SET[i];
+        if let Some(path) = &config.trace {
+            let filter = config.trace_filter().unwrap();
+            match Trace::create(path, filter, config.trace_last().unwrap()) {
+                Ok(trace) => vm.trace = Some(trace),
+                Err(err) => println!("Could not create {}: {}", path.display(), err),
+            }
+        }
+
+        if let Some(path) = config.symbols_path() {
+            match SymbolMap::load(&path) {
+                Ok(symbols) => vm.set_symbols(symbols),
+                Err(err) => println!("{}", err),
+            }
         }
 
-        self.load_rom(&rom_path);
+        if let Some(path) = &config.record_audio {
+            match AudioCapture::create(path, config.tone_settings().unwrap()) {
+                Ok(capture) => vm.audio_capture = Some(capture),
+                Err(err) => println!("Could not create {}: {}", path.display(), err),
+            }
+        }
+
+        vm
+    }
+
+    pub fn run(&mut self, rom_path: String) {
+        self.start(&rom_path);
 
         loop {
             self.cpu_timer.tick();
-            self.should_draw = false;
+            self.frame_timer.tick();
             self.handle_events();
+            self.poll_debugger();
 
+            // A halted machine keeps its timers and display as they were.
+            let running = !self.debugger.is_halted();
             if self.cpu_timer.get_value() == 0 {
-                self.one_cycle();
+                if running {
+                    self.one_cycle();
+                }
                 self.cpu_timer.set_value(1);
             }
 
+            if self.frame_timer.get_value() == 0 {
+                if running {
+                    self.end_frame();
+                }
+                // Drawn while halted too, so edits show up.
+                self.render_debug_window();
+                self.frame_timer.set_value(1);
+            }
+
             if self.done {
                 break;
             }
         }
+
+        self.finish();
+    }
+
+    /// Runs `frames` frames of emulated time as fast as possible, with no window, input
+    /// or sound device, stopping early if the ROM faults. Recordings and traces are
+    /// written as in a normal run.
+    pub fn run_headless(&mut self, rom_path: String, frames: usize) {
+        self.start(&rom_path);
+        self.run_frames(frames);
+        self.finish();
+    }
+
+    fn start(&mut self, rom_path: &String) {
+        self.reset();
+        // A debug adapter client can load the ROM instead.
+        if !rom_path.is_empty() {
+            self.load_rom(rom_path);
+        }
+        self.reset_history();
+        if let Some(path) = self.load_state.take() {
+            match MachineState::load(&path) {
+                Ok(state) => self.load_machine_state(&state),
+                Err(err) => println!("{}", err),
+            }
+        }
+    }
+
+    fn run_frames(&mut self, frames: usize) {
+        for _ in 0..frames {
+            for _ in 0..CYCLES_PER_FRAME {
+                if !self.debugger.is_halted() {
+                    self.one_cycle();
+                }
+            }
+            if self.debugger.is_halted() {
+                break;
+            }
+            self.end_frame();
+        }
+    }
+
+    fn finish(&mut self) {
+        self.stop_recording();
+        if let Some(trace) = self.trace.take() {
+            if let Err(err) = trace.finish() {
+                println!("Could not write trace: {}", err);
+            }
+        }
+        if let Some(capture) = self.audio_capture.take() {
+            if let Err(err) = capture.finish() {
+                println!("Could not finish audio recording: {}", err);
+            }
+        }
+    }
+
+    /// Puts the machine back in its power-on state, with the font loaded and nothing
+    /// else in memory.
+    fn reset(&mut self) {
+        for byte in self.memory.iter_mut() {
+            *byte = 0;
+        }
+        self.memory[..FONT_SET.len()].copy_from_slice(&FONT_SET);
+        for register in self.registers.iter_mut() {
+            *register = 0;
+        }
+        for address in self.stack.iter_mut() {
+            *address = 0;
+        }
+        self.index = 0;
+        self.pc = ROM_START as Word;
+        self.stack_pointer = 0;
+        self.delay_timer.set_value(0);
+        self.sound_timer.set_value(0);
+        self.waiting_for_key = false;
+        self.graphics.clear();
+        self.should_draw = true;
+    }
+
+    /// Starts the record for reverse stepping from the machine as it is now. Called
+    /// once the ROM is in memory, so stepping back never unloads it.
+    fn reset_history(&mut self) {
+        if self.history.is_some() {
+            let state = self.machine_state();
+            if let Some(history) = &mut self.history {
+                history.reset(state);
+            }
+        }
     }
 
     fn one_cycle(&mut self) {
         if self.waiting_for_key {
             self.check_key_press();
         } else {
-            self.update_timers();
-            self.run_next_instruction();
+            let at_breakpoint = self.debugger.breakpoint_hit(self.pc, self);
+            if !self.debugger.before_instruction(self.pc, at_breakpoint) {
+                if at_breakpoint {
+                    self.dump_trace(&format!("Breakpoint at {:03x}", self.pc));
+                }
+                return;
+            }
+            if let Err(fault) = self.run_next_instruction() {
+                self.crash(fault);
+                return;
+            }
+        }
+        // An Fx0A wait counts as part of its instruction.
+        if !self.waiting_for_key {
+            self.record_history();
+            let watch_hit = self.debugger.watchpoint_hit(self);
+            if let Some((_, address)) = watch_hit {
+                self.dump_trace(&format!("Watchpoint hit at {:03x}", address));
+            }
+            self.debugger.after_instruction(watch_hit);
+        }
+    }
 
-            if self.should_draw {
-                self.graphics.render();
+    /// Adds an executed instruction to the trace, with the registers that changed since
+    /// `before`.
+    fn trace_instruction(
+        &mut self,
+        pc: Word,
+        word: Word,
+        instruction: Option<&Instruction>,
+        before: [Word; REGISTER_COUNT],
+    ) {
+        let after = self.register_values();
+        let changes: Vec<(Register, Word)> = Register::all()
+            .into_iter()
+            .filter(|register| *register != Register::Pc)
+            .filter(|register| before[register.index()] != after[register.index()])
+            .map(|register| (register, after[register.index()]))
+            .collect();
+        self.recent
+            .record(pc, word, instruction, &changes)
+            .expect("a ring buffer is not written out");
+        if let Some(trace) = &mut self.trace {
+            if let Err(err) = trace.record(pc, word, instruction, &changes) {
+                println!("Could not write trace: {}", err);
+                self.trace = None;
             }
+        }
+    }
 
-            if self.sound_timer.get_value() > 0 {
-                self.audio.start_beep();
-            } else {
-                self.audio.stop_beep();
+    fn dump_trace(&mut self, reason: &str) {
+        if let Some(trace) = &mut self.trace {
+            if let Err(err) = trace.dump(reason) {
+                println!("Could not write trace: {}", err);
+                self.trace = None;
             }
         }
     }
 
-    fn check_key_press(&mut self) {
-        if let Some(i) = self.input.get_first_pressed_key() {
-            self.registers[self.key_register as usize] = i as u8;
-            self.waiting_for_key = false;
+    fn register_values(&self) -> [Word; REGISTER_COUNT] {
+        let mut values = [0; REGISTER_COUNT];
+        for register in Register::all() {
+            values[register.index()] = self.read_register(register);
+        }
+        values
+    }
+
+    fn machine_state(&self) -> MachineState {
+        MachineState {
+            registers: self.register_values(),
+            stack: self.stack.clone(),
+            memory: self.memory.clone(),
+            vram: *self.graphics.vram(),
+        }
+    }
+
+    fn restore_state(&mut self, state: &MachineState) {
+        for register in Register::all() {
+            self.write_register(register, state.register(register));
+        }
+        self.stack.copy_from_slice(&state.stack);
+        self.memory.copy_from_slice(&state.memory);
+        *self.graphics.vram_mut() = state.vram;
+        self.waiting_for_key = false;
+        self.should_draw = true;
+    }
+
+    /// Replaces the whole machine with a saved state.
+    fn load_machine_state(&mut self, state: &MachineState) {
+        self.restore_state(state);
+        if let Some(history) = &mut self.history {
+            history.reset(state.clone());
+        }
+    }
+
+    fn record_history(&mut self) {
+        let registers = self.register_values();
+        if let Some(history) = &mut self.history {
+            history.record(&registers, &self.stack, &self.memory, self.graphics.vram());
+        }
+    }
+
+    fn open_debug_window(&mut self) {
+        let window = match &self.sdl_context {
+            Some(sdl_context) => DebugWindow::new(sdl_context),
+            None => return,
+        };
+        self.input.set_debug_window(window.id());
+        self.debug_window = Some(window);
+    }
+
+    fn render_debug_window(&mut self) {
+        if let Some(mut window) = self.debug_window.take() {
+            if window.is_visible() {
+                window.render(&self.debugger, self, self.graphics.palette());
+            }
+            self.debug_window = Some(window);
+        }
+    }
+
+    fn poll_debugger(&mut self) {
+        if let Some(mut gdb) = self.gdb.take() {
+            gdb.poll(self);
+            self.gdb = Some(gdb);
+        }
+        if let Some(mut dap) = self.dap.take() {
+            dap.poll(self);
+            self.dap = Some(dap);
+        }
+    }
+
+    /// Runs once per 60Hz frame: presents the display, plays the frame's audio and
+    /// counts down the timers.
+    fn end_frame(&mut self) {
+        let keypad_changed = self.graphics.set_keypad_keys(self.input.pressed_keys());
+        if self.should_draw || self.graphics.is_fading() || keypad_changed {
+            self.graphics.render();
+            self.should_draw = false;
+        }
+        self.record_frame();
+
+        let sound_on = self.sound_timer.get_value() > 0;
+        self.audio.play_frame(sound_on);
+        if let Some(capture) = &mut self.audio_capture {
+            if let Err(err) = capture.add_frame(sound_on) {
+                println!("Could not write audio: {}", err);
+                self.audio_capture = None;
+            }
         }
+
+        self.update_timers();
+        self.input.end_frame();
     }
 
-    fn run_next_instruction(&mut self) {
-        let word = self.fetch();
-        let decode_result = decode(word);
-        match decode_result {
-            Ok(instruction) => {
-                self.execute(instruction);
+    fn record_frame(&mut self) {
+        if self.gif_recorder.is_none() && self.frame_dump.is_none() {
+            return;
+        }
+
+        let (frame, width, height) = self.graphics.scaled_frame(RECORDING_SCALE);
+        if let Some(recorder) = &mut self.gif_recorder {
+            if let Err(err) = recorder.add_frame(&frame) {
+                println!("{}", err);
+                self.gif_recorder = None;
             }
-            Err(err) => {
-                println!("Error decoding instruction: {:?}", err);
+        }
+        if let Some(dump) = &mut self.frame_dump {
+            if let Err(err) = dump.add_frame(&frame, width, height) {
+                println!("{}", err);
+                self.frame_dump = None;
+            }
+        }
+    }
+
+    fn start_recording(&mut self, path: &Path) {
+        let (_, width, height) = self.graphics.scaled_frame(RECORDING_SCALE);
+        match GifRecorder::create(path, width, height) {
+            Ok(recorder) => {
+                println!("Recording to {}", path.display());
+                self.gif_recorder = Some(recorder);
+            }
+            Err(err) => println!("{}", err),
+        }
+    }
+
+    fn stop_recording(&mut self) {
+        if let Some(recorder) = self.gif_recorder.take() {
+            match recorder.finish() {
+                Ok(path) => println!("Saved recording {}", path.display()),
+                Err(err) => println!("{}", err),
+            }
+        }
+    }
+
+    fn check_key_press(&mut self) {
+        while let Some(event) = self.input.next_key_event() {
+            if let Some(key) = self.key_wait.handle(event) {
+                self.registers[self.key_register as usize] = key as u8;
+                self.waiting_for_key = false;
+                break;
             }
+        }
+    }
+
+    /// Runs the instruction at the program counter. An instruction that faults changes
+    /// nothing.
+    fn run_next_instruction(&mut self) -> Result<(), Fault> {
+        let pc = self.pc;
+        let word = self.fetch()?;
+        let before = self.register_values();
+        let instruction = decode(word).ok();
+        let result = match instruction {
+            Some(instruction) => self.execute(instruction),
+            None => Err(Fault::InvalidInstruction { opcode: word }),
         };
+        self.trace_instruction(pc, word, instruction.as_ref(), before);
+        result
+    }
+
+    /// Stops the machine, where a debugger can look at it, and writes a crash report.
+    fn crash(&mut self, fault: Fault) {
+        let reason = format!("{} at {:03x}", fault, self.pc);
+        println!("{}", reason);
+        self.dump_trace(&reason);
+        let state = self.machine_state();
+        let image = self.graphics.vram_image();
+        match write_crash_dump(
+            &self.crash_dir,
+            fault,
+            &state,
+            &self.recent.buffered(),
+            &image,
+        ) {
+            Ok(path) => println!("Wrote crash report {}", path.display()),
+            Err(err) => println!("{}", err),
+        }
+        self.debugger.halt(StopReason::Fault(fault));
     }
 
     fn update_timers(&mut self) {
-        self.delay_timer.tick();
-        self.sound_timer.tick();
+        self.delay_timer.count_down();
+        self.sound_timer.count_down();
     }
 
     fn load_rom(&mut self, rom_path: &String) {
@@ -134,22 +568,68 @@ impl VirtualMachine {
     }
 
     fn handle_events(&mut self) {
-        self.done = self.input.process_input();
+        for hotkey in self.input.process_input() {
+            match hotkey {
+                Hotkey::Quit => self.done = true,
+                Hotkey::CycleTheme => {
+                    self.graphics.cycle_palette();
+                    self.should_draw = true;
+                }
+                Hotkey::Screenshot => match save_screenshot(&self.graphics, &self.screenshot_dir) {
+                    Ok(paths) => {
+                        for path in paths {
+                            println!("Saved screenshot {}", path.display());
+                        }
+                    }
+                    Err(err) => println!("{}", err),
+                },
+                Hotkey::ToggleMute => self.audio.toggle_mute(),
+                Hotkey::ToggleKeypad => {
+                    let shown = self.graphics.toggle_keypad();
+                    self.input.set_keypad_enabled(shown);
+                    self.should_draw = true;
+                }
+                Hotkey::ToggleRecording => {
+                    if self.gif_recorder.is_some() {
+                        self.stop_recording();
+                    } else if let Err(err) = fs::create_dir_all(&self.screenshot_dir) {
+                        println!("{}", err);
+                    } else {
+                        let name = format!("chip8-{}.gif", timestamp());
+                        self.start_recording(&self.screenshot_dir.join(name));
+                    }
+                }
+                Hotkey::ToggleDebugWindow => match &mut self.debug_window {
+                    Some(window) => window.toggle(),
+                    None => self.open_debug_window(),
+                },
+                // Macros are recorded and played back inside the input layer.
+                Hotkey::RecordMacro | Hotkey::PlayMacro => {}
+            }
+        }
+
+        if let Some(mut window) = self.debug_window.take() {
+            for event in self.input.take_debug_window_events() {
+                window.handle_event(&event, self);
+            }
+            self.debug_window = Some(window);
+        }
     }
 
-    fn fetch(&mut self) -> Word {
+    fn fetch(&mut self) -> Result<Word, Fault> {
+        self.access_memory(self.pc as usize, 2, false)?;
         let word = u16::from_be_bytes([
             self.memory[self.pc as usize],
             self.memory[(self.pc + 1) as usize],
         ]);
-        word
+        Ok(word)
     }
 
     fn inc_pc(&mut self) {
         self.pc += 2;
     }
 
-    fn execute(&mut self, instruction: Instruction) {
+    fn execute(&mut self, instruction: Instruction) -> Result<(), Fault> {
         match instruction {
             Instruction::Cls => {
                 self.graphics.clear();
@@ -157,11 +637,17 @@ impl VirtualMachine {
                 self.should_draw = true;
             }
             Instruction::Ret => {
+                if self.stack_pointer == 0 {
+                    return Err(Fault::StackUnderflow);
+                }
                 self.stack_pointer -= 1;
                 self.pc = self.stack[self.stack_pointer as usize].into();
             }
             Instruction::Jp { address } => self.pc = address,
             Instruction::Call { address } => {
+                if self.stack_pointer as usize >= self.stack.len() {
+                    return Err(Fault::StackOverflow);
+                }
                 self.stack[self.stack_pointer as usize] = self.pc + 2;
                 self.stack_pointer += 1;
                 self.pc = address;
@@ -269,6 +755,7 @@ impl VirtualMachine {
                 self.inc_pc();
             }
             Instruction::DrwVxVy { vx, vy, n } => {
+                self.access_memory(self.index as usize, n as usize, false)?;
                 let sprite_bytes =
                     &self.memory[self.index as usize..(self.index + n as u16) as usize];
                 let x = self.registers[vx as usize];
@@ -297,6 +784,8 @@ impl VirtualMachine {
                 self.inc_pc();
             }
             Instruction::LoadKeyVx { vx } => {
+                self.input.clear_key_events();
+                self.key_wait.start();
                 self.waiting_for_key = true;
                 self.key_register = vx;
                 self.inc_pc();
@@ -318,6 +807,7 @@ impl VirtualMachine {
                 self.inc_pc();
             }
             Instruction::LoadBVx { vx } => {
+                self.access_memory(self.index as usize, 3, true)?;
                 let value = self.registers[vx as usize];
                 self.memory[self.index as usize] = value / 100;
                 self.memory[self.index as usize + 1usize] = (value % 100) / 10;
@@ -325,20 +815,206 @@ impl VirtualMachine {
                 self.inc_pc();
             }
             Instruction::StoreVxArray { vx } => {
+                self.access_memory(self.index as usize, vx as usize + 1, true)?;
                 for n in 0..=vx {
                     self.memory[(self.index + n as u16) as usize] = self.registers[n as usize];
                 }
                 self.inc_pc();
             }
             Instruction::ReadVxArray { vx } => {
+                self.access_memory(self.index as usize, vx as usize + 1, false)?;
                 for n in 0..=vx {
                     self.registers[n as usize] = self.memory[(self.index + n as u16) as usize];
                 }
                 self.inc_pc();
             }
+            Instruction::LoadAudioPattern => {
+                let start = self.index as usize;
+                self.access_memory(start, 16, false)?;
+                let mut pattern = [0; 16];
+                pattern.copy_from_slice(&self.memory[start..start + 16]);
+                self.audio.set_pattern(pattern);
+                if let Some(capture) = &mut self.audio_capture {
+                    capture.set_pattern(pattern);
+                }
+                self.inc_pc();
+            }
+            Instruction::SetPitchVx { vx } => {
+                let pitch = self.registers[vx as usize];
+                self.audio.set_pitch(pitch);
+                if let Some(capture) = &mut self.audio_capture {
+                    capture.set_pitch(pitch);
+                }
+                self.inc_pc();
+            }
             _ => {
                 self.inc_pc();
             } // NOP
         }
+        Ok(())
+    }
+
+    /// Checks that the current instruction can read or write `length` bytes from
+    /// `address`, and tells the debugger about the access.
+    fn access_memory(&mut self, address: usize, length: usize, write: bool) -> Result<(), Fault> {
+        if address + length > self.memory.len() {
+            return Err(Fault::MemoryOutOfBounds { address, length });
+        }
+        self.debugger.memory_access(address, length, write);
+        Ok(())
+    }
+}
+
+impl Target for VirtualMachine {
+    fn debugger(&mut self) -> &mut Debugger {
+        &mut self.debugger
+    }
+
+    fn read_register(&self, register: Register) -> Word {
+        match register {
+            Register::V(n) => self.registers[n] as Word,
+            Register::I => self.index,
+            Register::Pc => self.pc,
+            Register::Sp => self.stack_pointer,
+            Register::Dt => self.delay_timer.get_value() as Word,
+            Register::St => self.sound_timer.get_value() as Word,
+        }
+    }
+
+    fn write_register(&mut self, register: Register, value: Word) -> bool {
+        match register {
+            Register::V(n) => self.registers[n] = value as u8,
+            Register::I => self.index = value,
+            Register::Pc => self.pc = value,
+            Register::Sp if value as usize > self.stack.len() => return false,
+            Register::Sp => self.stack_pointer = value,
+            Register::Dt => self.delay_timer.set_value(value as u8),
+            Register::St => self.sound_timer.set_value(value as u8),
+        }
+        true
+    }
+
+    fn read_memory(&self, address: usize, length: usize) -> Vec<u8> {
+        let start = address.min(self.memory.len());
+        let end = start.saturating_add(length).min(self.memory.len());
+        self.memory[start..end].to_vec()
+    }
+
+    fn write_memory(&mut self, address: usize, data: &[u8]) -> bool {
+        let end = match address.checked_add(data.len()) {
+            Some(end) => end,
+            None => return false,
+        };
+        match self.memory.get_mut(address..end) {
+            Some(memory) => {
+                memory.copy_from_slice(data);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn call_stack(&self) -> Vec<Word> {
+        let depth = (self.stack_pointer as usize).min(self.stack.len());
+        self.stack[..depth]
+            .iter()
+            .rev()
+            .cloned()
+            .collect()
+    }
+
+    fn load_program(&mut self, rom: &[u8]) -> bool {
+        if rom.len() > self.memory.len() - ROM_START {
+            return false;
+        }
+        self.reset();
+        self.memory[ROM_START..ROM_START + rom.len()].copy_from_slice(rom);
+        self.reset_history();
+        true
+    }
+
+    fn step_back(&mut self) -> bool {
+        let state = match self.history.as_mut().and_then(History::step_back) {
+            Some(state) => state.clone(),
+            None => return false,
+        };
+        self.restore_state(&state);
+        true
+    }
+
+    fn symbols(&self) -> &SymbolMap {
+        &self.symbols
+    }
+
+    fn set_symbols(&mut self, symbols: SymbolMap) {
+        self.recent.set_symbols(symbols.clone());
+        if let Some(trace) = &mut self.trace {
+            trace.set_symbols(symbols.clone());
+        }
+        self.symbols = symbols;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs `rom` headless for `frames` frames with the beeper recorded, returning the
+    /// recorded samples.
+    fn run_headless(name: &str, rom: &[u8], frames: usize) -> Vec<i16> {
+        let rom_path = std::env::temp_dir().join(format!("rust_chip8_{}.ch8", name));
+        let wav_path = rom_path.with_extension("wav");
+        fs::write(&rom_path, rom).unwrap();
+        let mut config = Config::default();
+        config.record_audio = Some(wav_path.clone());
+
+        let mut vm = VirtualMachine::new(None, &config);
+        vm.run_headless(rom_path.to_string_lossy().to_string(), frames);
+        let bytes = fs::read(&wav_path).unwrap();
+        fs::remove_file(&rom_path).unwrap();
+        fs::remove_file(&wav_path).unwrap();
+        bytes[44..]
+            .chunks_exact(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]))
+            .collect()
+    }
+
+    #[test]
+    fn stepping_back_to_the_start_keeps_the_rom() {
+        // LD V0, 5; ADD V0, 1; JP 0x202
+        let rom = [0x60, 0x05, 0x70, 0x01, 0x12, 0x02];
+        let mut vm = VirtualMachine::new(None, &Config::default());
+        vm.history = Some(History::new(HISTORY_LENGTH));
+        assert!(vm.load_program(&rom));
+        vm.run_frames(1);
+
+        while vm.step_back() {}
+        assert_eq!(vm.read_register(Register::Pc), ROM_START as Word);
+        assert_eq!(vm.read_register(Register::V(0)), 0);
+        assert_eq!(&vm.memory[ROM_START..ROM_START + rom.len()], &rom);
+    }
+
+    #[test]
+    fn writes_past_the_end_of_memory_are_refused() {
+        let mut vm = VirtualMachine::new(None, &Config::default());
+        assert!(!vm.write_memory(usize::MAX, &[1, 2]));
+        assert!(!vm.write_memory(MEMORY_SIZE - 1, &[1, 2]));
+        assert!(vm.write_memory(MEMORY_SIZE - 2, &[1, 2]));
+    }
+
+    #[test]
+    fn headless_run_records_the_sound_timer() {
+        // LD V0, 5; LD ST, V0; JP 0x204
+        let rom = [0x60, 0x05, 0xf0, 0x18, 0x12, 0x04];
+        let samples = run_headless("sound_timer", &rom, 10);
+        assert_eq!(samples.len(), 10 * SAMPLES_PER_FRAME);
+        let frames: Vec<&[i16]> = samples.chunks(SAMPLES_PER_FRAME).collect();
+
+        for frame in &frames[..5] {
+            assert!(frame.iter().any(|&s| s > 0) && frame.iter().any(|&s| s < 0));
+        }
+        for frame in &frames[6..] {
+            assert!(frame.iter().all(|&s| s == 0));
+        }
     }
 }
@@ -0,0 +1,71 @@
+use std::io::{self, Seek, SeekFrom, Write};
+
+const HEADER_SIZE: u32 = 44;
+
+/// Writes mono 16-bit PCM WAV data. The header's sizes are filled in by `finish`.
+pub struct WavWriter<W: Write + Seek> {
+    out: W,
+    samples: u32,
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(mut out: W, sample_rate: u32) -> io::Result<Self> {
+        let bytes_per_sample = 2;
+        out.write_all(b"RIFF")?;
+        out.write_all(&0u32.to_le_bytes())?;
+        out.write_all(b"WAVEfmt ")?;
+        out.write_all(&16u32.to_le_bytes())?;
+        out.write_all(&1u16.to_le_bytes())?; // PCM
+        out.write_all(&1u16.to_le_bytes())?; // mono
+        out.write_all(&sample_rate.to_le_bytes())?;
+        out.write_all(&(sample_rate * bytes_per_sample).to_le_bytes())?;
+        out.write_all(&(bytes_per_sample as u16).to_le_bytes())?;
+        out.write_all(&16u16.to_le_bytes())?;
+        out.write_all(b"data")?;
+        out.write_all(&0u32.to_le_bytes())?;
+
+        Ok(WavWriter { out, samples: 0 })
+    }
+
+    /// Writes samples in the range -1.0 to 1.0.
+    pub fn write_samples(&mut self, samples: &[f32]) -> io::Result<()> {
+        for sample in samples {
+            let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            self.out.write_all(&value.to_le_bytes())?;
+        }
+        self.samples += samples.len() as u32;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        let data_size = self.samples * 2;
+        self.out.seek(SeekFrom::Start(4))?;
+        self.out
+            .write_all(&(HEADER_SIZE - 8 + data_size).to_le_bytes())?;
+        self.out.seek(SeekFrom::Start(40))?;
+        self.out.write_all(&data_size.to_le_bytes())?;
+        self.out.seek(SeekFrom::End(0))?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn header_sizes_match_samples() {
+        let mut wav = WavWriter::new(Cursor::new(Vec::new()), 44100).unwrap();
+        wav.write_samples(&[0.0, 1.0, -1.0]).unwrap();
+        let bytes = wav.finish().unwrap().into_inner();
+
+        assert_eq!(bytes.len(), HEADER_SIZE as usize + 6);
+        assert_eq!(&bytes[0..4], b"RIFF");
+        assert_eq!(&bytes[4..8], &(36u32 + 6).to_le_bytes());
+        assert_eq!(&bytes[24..28], &44100u32.to_le_bytes());
+        assert_eq!(&bytes[40..44], &6u32.to_le_bytes());
+        assert_eq!(&bytes[44..], &[0, 0, 0xff, 0x7f, 0x01, 0x80]);
+    }
+}